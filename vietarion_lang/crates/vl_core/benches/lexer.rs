@@ -0,0 +1,37 @@
+// cargo bench -p vl_core --bench lexer
+//
+// Mốc so sánh với lexer cũ dùng Vec<char> và String (commit ngay trước lexer streaming, 578b2b8~1),
+// đo trên cùng một máy với đoạn mã 5.6 MB dưới đây:
+//   lexer cũ:  lex ~330 ms, lex+parse ~810 ms (~6.8 MB/s)
+//   lexer mới: lex ~160 ms, lex+parse ~440 ms (~12.8 MB/s)
+// Đoạn mã chỉ dùng cú pháp mà cây cũ đã có (chưa có `*`) để hai bên phân tích cùng 120000 lệnh.
+// Đo lại mốc cũ: `git worktree add /tmp/cu 578b2b8~1`, chép file này và khai báo [[bench]] sang đó,
+// rồi đổi hai dòng đo thành
+//   let n = Lexer::new(&src).scan_tokens().map(|t| t.len()).unwrap_or(0);
+//   let stmts = Lexer::new(&src).scan_tokens().ok().and_then(|t| Parser::new(t).parse().ok()).map(|s| s.len()).unwrap_or(0);
+use std::time::Instant;
+use vl_core::lexer::Lexer;
+use vl_core::parser::Parser;
+
+fn script(lines: usize) -> String {
+    let mut src = String::new();
+    for i in 0..lines {
+        src.push_str(&format!("tb bien_{} = {} + {} + 2 // chu thich\n", i % 97, i, i % 13));
+        src.push_str(&format!("neu bien_{} > 10 {{\n  in(\"Máu hiện tại: {{bien_{}}}\")\n}}\n", i % 97, i % 97));
+    }
+    src
+}
+
+fn main() {
+    let src = script(60_000);
+    let mb = src.len() as f64 / (1024.0 * 1024.0);
+    for _ in 0..3 {
+        let t = Instant::now();
+        let n = Lexer::new(&src).count();
+        let lex = t.elapsed();
+        let t = Instant::now();
+        let stmts = Parser::new(Lexer::new(&src)).parse().map(|s| s.len()).unwrap_or(0);
+        let total = t.elapsed();
+        println!("{:.1} MB, {} token, {} lenh: lex {:?}, lex+parse {:?} ({:.1} MB/s)", mb, n, stmts, lex, total, mb / total.as_secs_f64());
+    }
+}
@@ -1,18 +1,74 @@
-use crate::token::TokenType;
+use crate::token::{Span, TokenType};
+use vl_vm::Type;
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Literal(TokenType),
     Variable(String),
     Binary { left: Box<Expr>, op: TokenType, right: Box<Expr> },
+    Unary { op: TokenType, right: Box<Expr> },
+    // `va` / `hoac`: vế phải chỉ được tính khi cần
+    Logical { left: Box<Expr>, op: TokenType, right: Box<Expr> },
     Call { callee: String, args: Vec<Expr> },
+    Interpolated(Vec<InterpPart>),
+    List(Vec<Expr>),
+    // `{ khoá: giá trị, ... }` giữ nguyên thứ tự viết
+    Map(Vec<(Expr, Expr)>),
+    // `ds[i]`, và `ds[a:b]` với hai đầu đều có thể bỏ trống
+    Index { object: Box<Expr>, index: Box<Expr> },
+    Slice { object: Box<Expr>, start: Option<Box<Expr>>, end: Option<Box<Expr>> },
 }
 
+// Chuỗi nội suy: phần chữ giữ nguyên, phần biểu thức kèm định dạng tuỳ chọn như ".2" hay ">10"
+#[derive(Debug, Clone)]
+pub enum InterpPart {
+    Lit(String),
+    Expr(Expr, Option<String>),
+}
+
+/// Thuộc tính `#[ten]` hoặc `#[ten(a, b)]`; khối `#[{ ... }]` có tên là "{}".
+#[derive(Debug, Clone)]
+pub struct Attr {
+    pub name: String,
+    pub args: Vec<String>,
+    pub span: Span,
+}
+
+// Mỗi câu lệnh giữ `span` của token đầu tiên để compiler ghi vào bảng dòng
 #[derive(Debug, Clone)]
 pub enum Stmt {
-    VarDecl { name: String, init: Expr },
-    While { condition: Expr, body: Vec<Stmt> },
-    If { condition: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>> },
-    Function { name: String, params: Vec<String>, body: Vec<Stmt> },
-    Expression(Expr),
+    VarDecl { name: String, ty: Option<Type>, init: Expr, doc: Option<String>, span: Span },
+    // `x = ...` gán cho biến đã khai báo bằng `tb`
+    Assign { name: String, value: Expr, span: Span },
+    // `ds[i] = ...`, cả `m.ten = ...` vì `m.ten` chính là `m["ten"]`
+    IndexAssign { object: Expr, index: Expr, value: Expr, span: Span },
+    // Vòng lặp có thể mang nhãn `'ten:` để `dung 'ten` / `tiep 'ten` thoát ra từ vòng lồng bên trong
+    While { label: Option<String>, condition: Expr, body: Vec<Stmt>, span: Span },
+    // `cho i tu a den b buoc s { }`; với `truoc` thay cho `den` thì không chạy tới `b`
+    For { label: Option<String>, var: String, start: Expr, end: Expr, inclusive: bool, step: Option<Expr>, body: Vec<Stmt>, span: Span },
+    // `moi x trong ds { }` qua phần tử danh sách, ký tự của chuỗi hoặc khoá của bảng
+    ForEach { label: Option<String>, var: String, iterable: Expr, body: Vec<Stmt>, span: Span },
+    If { condition: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>>, span: Span },
+    // Tham số và giá trị trả về có thể kèm kiểu: `ham cong(a: nguyen, b: nguyen) -> nguyen`
+    Function { name: String, params: Vec<(String, Option<Type>)>, ret: Option<Type>, body: Vec<Stmt>, doc: Option<String>, span: Span },
+    // `kieu NguoiChoi { ten, mau, giap }`; tạo giá trị bằng `NguoiChoi("An", 100, 50)`
+    Record { name: String, fields: Vec<String>, doc: Option<String>, span: Span },
+    // `loai TrangThai { Song, Chet, BiThuong(mau) }`: mỗi biến thể kèm tên các giá trị nó mang
+    Enum { name: String, variants: Vec<(String, Vec<String>)>, doc: Option<String>, span: Span },
+    Return { value: Option<Expr>, span: Span },
+    Break { label: Option<String>, span: Span },
+    Continue { label: Option<String>, span: Span },
+    Expression { expr: Expr, span: Span },
+    // `body` là câu lệnh ngay sau thuộc tính, hoặc cả vùng tới `#[end]` với `#[ime]`
+    Attribute { attr: Attr, body: Vec<Stmt>, span: Span },
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::VarDecl { span, .. } | Stmt::Assign { span, .. } | Stmt::IndexAssign { span, .. } | Stmt::While { span, .. } | Stmt::For { span, .. } | Stmt::ForEach { span, .. } | Stmt::If { span, .. }
+            | Stmt::Function { span, .. } | Stmt::Record { span, .. } | Stmt::Enum { span, .. } | Stmt::Return { span, .. }
+            | Stmt::Break { span, .. } | Stmt::Continue { span, .. } | Stmt::Expression { span, .. } | Stmt::Attribute { span, .. } => *span,
+        }
+    }
 }
@@ -1,127 +1,682 @@
-use crate::ast::{Stmt, Expr};
-use crate::token::TokenType;
-use vl_vm::{Chunk, Value};
+use crate::ast::{Attr, Stmt, Expr, InterpPart};
+use crate::token::{Span, TokenType};
+use vl_bytecode::Value;
+use std::rc::Rc;
+use vl_vm::{Chunk, Enum, Function, Record, Type};
+use crate::VlError;
 
-pub struct Compiler { chunk: Chunk }
+// Hàm có sẵn được dịch thẳng thành một opcode riêng: (tên, opcode, số tham số)
+const BUILTINS: &[(&str, u8, usize)] = &[
+    ("in", 8, 1), ("in_lien", 25, 1), ("in_liền", 25, 1),
+    ("ngu", 21, 1), ("xoa", 22, 0), ("xóa", 22, 0), ("xoá", 22, 0),
+    ("nhap", 23, 0), ("doc_tep", 20, 1), ("đọc_tệp", 20, 1), ("ghi_file", 24, 2),
+    ("ngaunhien", 15, 0),
+    ("them", 45, 2), ("thêm", 45, 2), ("xoa_tai", 46, 2), ("xoá_tại", 46, 2), ("xóa_tại", 46, 2),
+    ("do_dai", 47, 1), ("độ_dài", 47, 1),
+    ("co_khoa", 49, 2), ("có_khoá", 49, 2), ("có_khóa", 49, 2), ("xoa_khoa", 50, 2), ("xoá_khoá", 50, 2), ("xóa_khóa", 50, 2),
+];
+
+// Vòng lặp đang biên dịch: các lệnh nhảy của `dung`/`tiep` chờ vá địa chỉ khi biết điểm đến
+struct Loop {
+    label: Option<String>,
+    // Số biến cục bộ lúc vào thân vòng; nhảy ra thì bỏ các biến khai báo sau đó
+    locals: usize,
+    continue_target: Option<usize>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// Biến cục bộ nằm trên stack theo thứ tự khai báo; `depth` là độ sâu phạm vi của khối khai báo nó
+struct Local { name: String, depth: usize, ty: Option<Type> }
+
+pub struct Compiler {
+    chunk: Chunk,
+    span: Span,
+    cfg: Vec<String>,
+    test_mode: bool,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    // Biến toàn cục đã khai báo bằng `tb` (hoặc tên hàm), để bắt lỗi gán cho biến chưa có
+    globals: Vec<(String, Option<Type>)>,
+    // Tên và kiểu trả về của hàm đang biên dịch
+    function: Option<(String, Option<Type>)>,
+    errors: Vec<VlError>,
+    pub warnings: Vec<VlError>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self { Self::new() }
+}
 
 impl Compiler {
-    pub fn new() -> Self { Self { chunk: Chunk { code: vec![], constants: vec![], names: vec![] } } }
-    
-    pub fn compile(&mut self, stmts: Vec<Stmt>) -> Chunk {
-        self.chunk.code.push(11); 
-        let main_jump_patch = self.chunk.code.len();
-        self.emit_u16(0);
-
-        for stmt in &stmts {
-            if let Stmt::Function { name, body, .. } = stmt {
-                let start_addr = self.chunk.code.len();
+    pub fn new() -> Self {
+        Self {
+            chunk: Chunk { code: vec![], constants: vec![], names: vec![], lines: vec![], functions: vec![], types: vec![], records: vec![], enums: vec![] },
+            span: Span::default(),
+            cfg: vec![],
+            test_mode: false,
+            locals: vec![],
+            scope_depth: 0,
+            loops: vec![],
+            globals: vec![],
+            function: None,
+            errors: vec![],
+            warnings: vec![],
+        }
+    }
+
+    /// Cờ cấu hình cho `#[neu_cau_hinh(...)]`, ví dụ `debug`.
+    pub fn with_cfg(mut self, flags: &[String]) -> Self { self.cfg = flags.to_vec(); self }
+
+    /// Biên dịch cả các câu lệnh `#[kiem_thu]`.
+    pub fn with_tests(mut self) -> Self { self.test_mode = true; self }
+
+    pub fn compile(&mut self, stmts: Vec<Stmt>) -> Result<Chunk, Vec<VlError>> {
+        // Hàm và kiểu ở cấp ngoài cùng (kể cả khi mang thuộc tính) được tách ra trước để dùng được trước khi khai báo
+        let mut functions = vec![];
+        let mut tests = vec![];
+        let mut main = vec![];
+        for stmt in stmts {
+            match stmt {
+                Stmt::Function { .. } => functions.push(stmt),
+                Stmt::Record { .. } | Stmt::Enum { .. } => self.declare_type(stmt),
+                Stmt::Attribute { attr, mut body, .. } if attr.name != "{}" && matches!(body.as_slice(), [Stmt::Record { .. } | Stmt::Enum { .. }]) => {
+                    if self.attribute_enabled(&attr) { self.declare_type(body.remove(0)); }
+                }
+                Stmt::Attribute { attr, mut body, .. } if attr.name != "{}" && matches!(body.as_slice(), [Stmt::Function { .. }]) => {
+                    if !self.attribute_enabled(&attr) { continue; }
+                    if let Some(Stmt::Function { name, params, span, .. }) = body.first() {
+                        if attr.name == "kiem_thu" {
+                            if params.is_empty() { tests.push(name.clone()); }
+                            else { self.error(*span, &format!("hàm kiểm thử '{}' không được nhận tham số", name)); }
+                        }
+                    }
+                    functions.append(&mut body);
+                }
+                _ => main.push(stmt),
+            }
+        }
+
+        for f in &functions {
+            if let Stmt::Function { name, params, span, .. } = f {
+                self.span = *span;
+                if self.chunk.functions.iter().any(|g| g.name == *name) {
+                    self.error(*span, &format!("hàm '{}' đã được khai báo", name));
+                }
+                if let Some(kind) = self.declared_type(name) {
+                    self.error(*span, &format!("tên '{}' đã được dùng cho một {}", name, kind));
+                }
+                self.check_builtin_name(name, *span);
+                let idx = self.chunk.functions.len();
+                self.chunk.functions.push(Function { name: name.clone(), arity: params.len(), start: 0 });
+                self.globals.push((name.clone(), None));
                 let n_idx = self.get_or_create_name(name.clone());
-                let c_idx = self.chunk.constants.len();
-                self.chunk.constants.push(Value::Number(start_addr as f64));
-                self.chunk.code.push(1); self.chunk.code.push(c_idx as u8);
-                self.chunk.code.push(6); self.chunk.code.push(n_idx as u8);
-                for s in body { self.compile_stmt(s.clone()); }
-                self.chunk.code.push(17); 
+                self.emit(31); self.emit_u8(idx, "hàm");
+                self.emit(6); self.emit_u16(n_idx);
             }
         }
 
-        let main_start = self.chunk.code.len();
-        self.chunk.code[main_jump_patch] = (main_start >> 8) as u8;
-        self.chunk.code[main_jump_patch+1] = (main_start & 0xFF) as u8;
+        // Vùng #[ime] ở cấp ngoài cùng được chạy trước mọi câu lệnh khác
+        let (priority, rest): (Vec<Stmt>, Vec<Stmt>) = main.into_iter()
+            .partition(|s| matches!(s, Stmt::Attribute { attr, .. } if attr.name == "ime"));
+        for stmt in priority.into_iter().chain(rest) { self.compile_stmt(stmt); }
+        // Chế độ kiểm thử: gọi từng hàm #[kiem_thu] sau khi chạy xong chương trình chính
+        for name in tests {
+            self.compile_expr(Expr::Call { callee: name, args: vec![] });
+            self.emit(9);
+        }
+        self.emit(0);
 
-        for stmt in stmts {
-            if !matches!(stmt, Stmt::Function { .. }) { self.compile_stmt(stmt); }
+        // Thân hàm nằm sau lệnh dừng nên chỉ chạy khi được gọi; biên dịch sau cùng để thấy mọi biến toàn cục
+        for (idx, f) in functions.into_iter().enumerate() {
+            if let Stmt::Function { name, params, ret, body, span, .. } = f {
+                self.span = span;
+                self.chunk.functions[idx].start = self.chunk.code.len();
+                self.compile_function(name, params, ret, body);
+            }
         }
-        self.chunk.code.push(0); 
-        self.chunk.clone()
+        if self.errors.is_empty() { Ok(self.chunk.clone()) } else { Err(std::mem::take(&mut self.errors)) }
+    }
+
+    // Ghi khai báo `kieu` hoặc `loai` vào chunk
+    fn declare_type(&mut self, stmt: Stmt) {
+        match stmt {
+            Stmt::Record { name, fields, span, .. } => {
+                if let Some(kind) = self.declared_type(&name) { self.error(span, &format!("{} '{}' đã được khai báo", kind, name)); }
+                self.check_builtin_name(&name, span);
+                self.chunk.records.push(Rc::new(Record { name, fields }));
+            }
+            Stmt::Enum { name, variants, span, .. } => {
+                for n in std::iter::once(&name).chain(variants.iter().map(|(v, _)| v)) {
+                    if let Some(kind) = self.declared_type(n) { self.error(span, &format!("{} '{}' đã được khai báo", kind, n)); }
+                    self.check_builtin_name(n, span);
+                }
+                let variants = variants.into_iter().map(|(name, fields)| Record { name, fields }).collect();
+                self.chunk.enums.push(Rc::new(Enum { name, variants }));
+            }
+            _ => {}
+        }
+    }
+
+    // Ghi vào phần tử (`ds[i] = x`, `them(ds, x)`) không qua phép gán, nên biến gốc có khai báo kiểu được kiểm tra lại sau khi ghi
+    fn recheck_variable(&mut self, target: &Expr) {
+        let mut root = target;
+        while let Expr::Index { object, .. } = root { root = object; }
+        let Expr::Variable(name) = root else { return };
+        let ty = match self.resolve_local(name) {
+            Some(slot) => self.locals[slot].ty.clone(),
+            None => self.globals.iter().find(|(n, _)| n == name).and_then(|(_, t)| t.clone()),
+        };
+        let Some(ty) = ty else { return };
+        self.compile_expr(Expr::Variable(name.clone()));
+        self.check_type(&ty, format!("biến '{}'", name));
+        self.emit(9);
+    }
+
+    // Lời gọi tới tên của hàm có sẵn luôn đi vào hàm có sẵn, nên hàm hay kiểu trùng tên sẽ không bao giờ được gọi
+    fn check_builtin_name(&mut self, name: &str, span: Span) {
+        if BUILTINS.iter().any(|(n, _, _)| *n == name) { self.error(span, &format!("tên '{}' trùng với hàm có sẵn", name)); }
+    }
+
+    // Tham số là các biến cục bộ đầu tiên của khung gọi; hết thân hàm mà chưa `tra_ve` thì trả về rỗng
+    fn compile_function(&mut self, name: String, params: Vec<(String, Option<Type>)>, ret: Option<Type>, body: Vec<Stmt>) {
+        let outer = std::mem::take(&mut self.locals);
+        let outer_depth = std::mem::replace(&mut self.scope_depth, 1);
+        for (slot, (param, ty)) in params.into_iter().enumerate() {
+            if let Some(ty) = &ty {
+                self.emit(29); self.emit_u8(slot, "biến cục bộ");
+                self.check_type(ty, format!("tham số '{}' của hàm '{}'", param, name));
+                self.emit(30); self.emit_u8(slot, "biến cục bộ");
+            }
+            self.locals.push(Local { name: param, depth: 1, ty });
+        }
+        self.function = Some((name, ret));
+        for s in body { self.compile_stmt(s); }
+        self.compile_return(None);
+        self.function = None;
+        self.locals = outer;
+        self.scope_depth = outer_depth;
+    }
+
+    fn emit(&mut self, byte: u8) {
+        self.chunk.code.push(byte);
+        self.chunk.lines.push((self.span.line as u32, self.span.col as u32));
+    }
+
+    // Toán hạng một byte; vượt quá thì báo lỗi biên dịch thay vì cắt bớt
+    fn emit_u8(&mut self, val: usize, what: &str) {
+        if val > u8::MAX as usize { self.limit_error(&format!("quá nhiều {} so với giới hạn {} của bytecode", what, u8::MAX)); }
+        self.emit(val as u8);
     }
 
     fn emit_u16(&mut self, val: usize) {
-        self.chunk.code.push((val >> 8) as u8);
-        self.chunk.code.push((val & 0xFF) as u8);
+        if val > u16::MAX as usize { self.limit_error(&format!("chương trình vượt quá giới hạn {} của bytecode", u16::MAX)); }
+        self.emit((val >> 8) as u8);
+        self.emit((val & 0xFF) as u8);
+    }
+
+    // Ghi địa chỉ hiện tại vào chỗ trống u16 của lệnh nhảy đã phát trước đó
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.chunk.code.len();
+        if target > u16::MAX as usize { self.limit_error(&format!("chương trình vượt quá giới hạn {} của bytecode", u16::MAX)); }
+        self.chunk.code[at] = (target >> 8) as u8;
+        self.chunk.code[at + 1] = (target & 0xFF) as u8;
     }
 
     fn compile_stmt(&mut self, stmt: Stmt) {
+        self.span = stmt.span();
         match stmt {
-            Stmt::VarDecl { name, init } => {
+            Stmt::VarDecl { name, ty, init, span, .. } => {
                 self.compile_expr(init);
-                let idx = self.get_or_create_name(name);
-                self.chunk.code.push(6); self.chunk.code.push(idx as u8);
+                if let Some(ty) = &ty { self.check_type(ty, format!("biến '{}'", name)); }
+                let scope_depth = self.scope_depth;
+                // `tb` trùng tên biến của cùng hàm (kể cả ở khối ngoài; mã ngoài hàm tính như một hàm) gán lại
+                // cho chính biến đó, để `tb i = i + 1` trong thân vòng lặp không tạo biến mới mỗi lượt.
+                // Biến giữ nguyên kiểu đã khai báo và giá trị mới phải khớp kiểu đó
+                let existing = match self.resolve_local(&name) {
+                    Some(slot) => Some((self.locals[slot].depth, self.locals[slot].ty.clone())),
+                    None if self.function.is_none() => self.globals.iter().find(|(n, _)| *n == name).map(|(_, t)| (0, t.clone())),
+                    None => None,
+                };
+                if let Some((depth, existing)) = existing {
+                    if let Some(old) = &existing { self.check_type(old, format!("biến '{}'", name)); }
+                    let mut msg = if depth == scope_depth { format!("biến '{}' đã được khai báo trong phạm vi này, dùng '{} = ...' để gán", name, name) }
+                        else { format!("biến '{}' đã được khai báo ở khối ngoài nên 'tb' ở đây gán lại cho biến đó, dùng '{} = ...' để gán", name, name) };
+                    match (&ty, &existing) {
+                        (Some(new), Some(old)) if new != old => msg += &format!("; biến vẫn giữ kiểu {}", old),
+                        (Some(new), None) => msg += &format!("; kiểu {} ở đây chỉ áp cho giá trị này", new),
+                        _ => {}
+                    }
+                    self.warn(span, &msg);
+                    self.set_variable(&name);
+                } else if scope_depth == 0 {
+                    self.globals.push((name.clone(), ty));
+                    self.set_variable(&name);
+                } else {
+                    // Giá trị khởi tạo nằm sẵn trên stack, chính là ô của biến cục bộ
+                    self.locals.push(Local { name, depth: scope_depth, ty });
+                }
             }
-            Stmt::If { condition, then_branch, .. } => {
+            Stmt::Assign { name, value, span } => {
+                self.compile_expr(value);
+                let ty = match self.resolve_local(&name) {
+                    Some(slot) => Some(self.locals[slot].ty.clone()),
+                    None => self.globals.iter().find(|(n, _)| *n == name).map(|(_, t)| t.clone()),
+                };
+                match ty {
+                    None => self.error(span, &format!("biến '{}' chưa được khai báo, dùng 'tb {} = ...'", name, name)),
+                    Some(Some(ty)) => self.check_type(&ty, format!("biến '{}'", name)),
+                    Some(None) => {}
+                }
+                self.set_variable(&name);
+            }
+            Stmt::IndexAssign { object, index, value, .. } => {
+                let target = object.clone();
+                self.compile_expr(object); self.compile_expr(index); self.compile_expr(value);
+                self.emit(43);
+                self.recheck_variable(&target);
+            }
+            Stmt::If { condition, then_branch, else_branch, .. } => {
                 self.compile_expr(condition);
-                self.chunk.code.push(10);
+                self.emit(10);
                 let if_patch = self.chunk.code.len(); self.emit_u16(0);
-                for s in then_branch { self.compile_stmt(s); }
-                let end_if = self.chunk.code.len();
-                self.chunk.code[if_patch] = (end_if >> 8) as u8;
-                self.chunk.code[if_patch+1] = (end_if & 0xFF) as u8;
+                self.compile_block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        // Nhánh `nguoc_lai neu` là một Stmt::If lồng bên trong nhánh else
+                        self.emit(11);
+                        let end_patch = self.chunk.code.len(); self.emit_u16(0);
+                        self.patch_jump(if_patch);
+                        self.compile_block(else_branch);
+                        self.patch_jump(end_patch);
+                    }
+                    None => self.patch_jump(if_patch),
+                }
             }
-            Stmt::While { condition, body } => {
+            Stmt::While { label, condition, body, .. } => {
                 let start = self.chunk.code.len();
                 self.compile_expr(condition);
-                self.chunk.code.push(10); 
+                self.emit(10); 
                 let exit_patch = self.chunk.code.len(); self.emit_u16(0);
-                for s in body { self.compile_stmt(s); }
-                self.chunk.code.push(11); self.emit_u16(start);
-                let end = self.chunk.code.len();
-                self.chunk.code[exit_patch] = (end >> 8) as u8;
-                self.chunk.code[exit_patch+1] = (end & 0xFF) as u8;
+                let lp = self.compile_loop_body(label, body, Some(start));
+                self.emit(11); self.emit_u16(start);
+                self.patch_jump(exit_patch);
+                for at in lp.breaks { self.patch_jump(at); }
+            }
+            // Biến đếm, giá trị cuối và bước là ba ô cục bộ liền nhau; lệnh 38 so sánh, lệnh 39 cộng bước
+            Stmt::For { label, var, start, end, inclusive, step, body, .. } => {
+                self.begin_scope();
+                self.compile_expr(start);
+                self.add_local(var);
+                self.compile_expr(end);
+                self.add_local("(den)".into());
+                match step {
+                    Some(step) => self.compile_expr(step),
+                    None => self.emit_constant(Value::Nguyen(1)),
+                }
+                self.add_local("(buoc)".into());
+                let slot = self.locals.len() - 3;
+                let loop_start = self.chunk.code.len();
+                self.emit(38); self.emit_u8(slot, "biến cục bộ"); self.emit(inclusive as u8);
+                let exit_patch = self.chunk.code.len(); self.emit_u16(0);
+                // `tiep` nhảy tới bước cộng, địa chỉ chỉ biết sau khi biên dịch xong thân vòng
+                let lp = self.compile_loop_body(label, body, None);
+                for at in lp.continues { self.patch_jump(at); }
+                self.emit(39); self.emit_u8(slot, "biến cục bộ");
+                self.emit(11); self.emit_u16(loop_start);
+                self.patch_jump(exit_patch);
+                for at in lp.breaks { self.patch_jump(at); }
+                self.end_scope();
+            }
+            // Giá trị đang duyệt, vị trí kế tiếp và biến lặp là ba ô cục bộ liền nhau; lệnh 40 lấy phần tử kế tiếp
+            Stmt::ForEach { label, var, iterable, body, .. } => {
+                self.begin_scope();
+                self.compile_expr(iterable);
+                self.add_local("(trong)".into());
+                self.emit_constant(Value::Nguyen(0));
+                self.add_local("(vi_tri)".into());
+                self.emit_constant(Value::Null);
+                self.add_local(var);
+                let slot = self.locals.len() - 3;
+                let loop_start = self.chunk.code.len();
+                self.emit(40); self.emit_u8(slot, "biến cục bộ");
+                let exit_patch = self.chunk.code.len(); self.emit_u16(0);
+                let lp = self.compile_loop_body(label, body, Some(loop_start));
+                self.emit(11); self.emit_u16(loop_start);
+                self.patch_jump(exit_patch);
+                for at in lp.breaks { self.patch_jump(at); }
+                self.end_scope();
+            }
+            Stmt::Break { label, span } => self.compile_loop_jump(label, span, true),
+            Stmt::Continue { label, span } => self.compile_loop_jump(label, span, false),
+            Stmt::Return { value, span } => {
+                if self.function.is_none() { self.error(span, "tra_ve chỉ dùng được trong hàm"); }
+                self.compile_return(value);
+            }
+            Stmt::Function { name, span, .. } => {
+                self.error(span, &format!("hàm '{}' phải được khai báo ở cấp ngoài cùng", name));
+            }
+            Stmt::Record { name, span, .. } => {
+                self.error(span, &format!("kiểu '{}' phải được khai báo ở cấp ngoài cùng", name));
+            }
+            Stmt::Enum { name, span, .. } => {
+                self.error(span, &format!("loại '{}' phải được khai báo ở cấp ngoài cùng", name));
+            }
+            Stmt::Expression { expr, .. } => { self.compile_expr(expr); self.emit(9); }
+            Stmt::Attribute { attr, body, .. } if self.attribute_enabled(&attr) => {
+                // Chỉ khối `#[{ }]` mở phạm vi mới; vùng `#[ime]` vẫn thuộc phạm vi bên ngoài
+                if attr.name == "{}" { self.compile_block(body); }
+                else { for s in body { self.compile_stmt(s); } }
             }
-            Stmt::Expression(expr) => { self.compile_expr(expr); }
             _ => {}
         }
     }
 
     fn compile_expr(&mut self, expr: Expr) {
         match expr {
-            Expr::Literal(TokenType::Int(v)) => {
-                let idx = self.chunk.constants.len();
-                self.chunk.constants.push(Value::Number(v as f64));
-                self.chunk.code.push(1); self.chunk.code.push(idx as u8);
-            }
-            Expr::Literal(TokenType::CHUOI(s)) => {
-                let idx = self.chunk.constants.len();
-                self.chunk.constants.push(Value::Str(s));
-                self.chunk.code.push(1); self.chunk.code.push(idx as u8);
-            }
-            Expr::Variable(name) => {
-                let idx = self.get_or_create_name(name);
-                self.chunk.code.push(7); self.chunk.code.push(idx as u8);
+            Expr::Literal(TokenType::Int(v)) => self.emit_constant(Value::Nguyen(v)),
+            Expr::Literal(TokenType::Float(v)) => self.emit_constant(Value::Thuc(v)),
+            Expr::Literal(TokenType::CHUOI(s)) => self.emit_constant(Value::Chuoi(s)),
+            Expr::Interpolated(parts) => {
+                let count = parts.len();
+                for part in parts {
+                    match part {
+                        InterpPart::Lit(s) => self.emit_constant(Value::Chuoi(s)),
+                        InterpPart::Expr(e, spec) => {
+                            self.compile_expr(e);
+                            if let Some(spec) = spec {
+                                let idx = self.add_constant(Value::Chuoi(spec));
+                                self.emit(27); self.emit_u16(idx);
+                            }
+                        }
+                    }
+                }
+                self.emit(28); self.emit_u8(count, "phần trong chuỗi nội suy");
             }
+            Expr::Variable(name) => match self.resolve_local(&name) {
+                Some(slot) => { self.emit(29); self.emit_u8(slot, "biến cục bộ"); }
+                None if self.variant(&name).is_some() => self.compile_variant(&name, vec![], false),
+                None => {
+                    let idx = self.get_or_create_name(name);
+                    self.emit(7); self.emit_u16(idx);
+                }
+            },
             Expr::Call { callee, args } => {
+                if let Some(idx) = self.chunk.records.iter().position(|r| r.name == callee) { return self.compile_record(idx, args) }
+                if self.variant(&callee).is_some() { return self.compile_variant(&callee, args, true) }
+                let Some(&(_, op, arity)) = BUILTINS.iter().find(|(n, _, _)| *n == callee) else { return self.compile_call(callee, args) };
+                if args.len() != arity {
+                    self.error(self.span, &format!("hàm '{}' cần {} tham số nhưng được gọi với {}", callee, arity, args.len()));
+                }
+                let target = if op == 45 { args.first().cloned() } else { None };
                 for arg in args { self.compile_expr(arg); }
-                match callee.as_str() {
-                    "in" => self.chunk.code.push(8),
-                    "in_dong" => self.chunk.code.push(25),
-                    "ngu" => self.chunk.code.push(21),
-                    "xoa" => self.chunk.code.push(22),
-                    "nhap" => self.chunk.code.push(23),
-                    "doc_file" => self.chunk.code.push(20),
-                    "ghi_file" => self.chunk.code.push(24),
-                    "ngaunhien" => self.chunk.code.push(15),
-                    _ => {
-                        let idx = self.get_or_create_name(callee);
-                        self.chunk.code.push(18); self.chunk.code.push(idx as u8);
+                self.emit(op);
+                if let Some(target) = target { self.recheck_variable(&target); }
+            }
+            Expr::List(items) => {
+                let count = items.len();
+                for item in items { self.compile_expr(item); }
+                self.emit(41); self.emit_u16(count);
+            }
+            Expr::Map(pairs) => {
+                let count = pairs.len();
+                for (key, value) in pairs { self.compile_expr(key); self.compile_expr(value); }
+                self.emit(48); self.emit_u16(count);
+            }
+            Expr::Index { object, index } => {
+                self.compile_expr(*object); self.compile_expr(*index);
+                self.emit(42);
+            }
+            // Đầu bỏ trống được thay bằng rỗng, lệnh 44 hiểu là đầu/cuối danh sách
+            Expr::Slice { object, start, end } => {
+                self.compile_expr(*object);
+                for bound in [start, end] {
+                    match bound {
+                        Some(b) => self.compile_expr(*b),
+                        None => self.emit_constant(Value::Null),
                     }
                 }
+                self.emit(44);
             }
             Expr::Binary { left, op, right } => {
                 self.compile_expr(*left); self.compile_expr(*right);
                 match op {
-                    TokenType::PLUS => self.chunk.code.push(2),
-                    TokenType::MINUS => self.chunk.code.push(3),
-                    TokenType::GT => self.chunk.code.push(12),
-                    TokenType::LT => self.chunk.code.push(13),
-                    TokenType::BANG => {
-                         // Xử lý gán biến kiểu cũ nếu cần
-                    }
+                    TokenType::PLUS => self.emit(2),
+                    TokenType::MINUS => self.emit(3),
+                    TokenType::STAR => self.emit(4),
+                    TokenType::SLASH => self.emit(5),
+                    TokenType::BACKSLASH => self.emit(53),
+                    TokenType::PERCENT => self.emit(32),
+                    TokenType::STAR_STAR => self.emit(33),
+                    TokenType::GT => self.emit(12),
+                    TokenType::LT => self.emit(13),
+                    TokenType::EQ_EQ => self.emit(14),
+                    TokenType::BANG_EQ => self.emit(16),
+                    TokenType::LE => self.emit(19),
+                    TokenType::GE => self.emit(26),
+                    _ => {}
+                }
+            }
+            // a va b:   a; nhảy-nếu-sai F; b; thành logic; nhảy E; F: sai; E:
+            // a hoac b: a; nhảy-nếu-sai R; đúng; nhảy E; R: b; thành logic; E:
+            Expr::Logical { left, op, right } => {
+                // Vế phải nằm ở nhánh vế trái đúng (va) hoặc nhánh vế trái sai (hoac), nhánh còn lại là hằng
+                let (when_true, when_false) = if op == TokenType::AND_AND { (Some(*right), None) } else { (None, Some(*right)) };
+                self.compile_expr(*left);
+                self.emit(10);
+                let false_patch = self.chunk.code.len(); self.emit_u16(0);
+                self.compile_logic_branch(when_true, true);
+                self.emit(11);
+                let end_patch = self.chunk.code.len(); self.emit_u16(0);
+                self.patch_jump(false_patch);
+                self.compile_logic_branch(when_false, false);
+                self.patch_jump(end_patch);
+            }
+            Expr::Unary { op, right } => {
+                self.compile_expr(*right);
+                match op {
+                    TokenType::MINUS => self.emit(34),
+                    TokenType::BANG => self.emit(35),
                     _ => {}
                 }
             }
             _ => {}
         }
     }
+    fn compile_loop_body(&mut self, label: Option<String>, body: Vec<Stmt>, continue_target: Option<usize>) -> Loop {
+        self.loops.push(Loop { label, locals: self.locals.len(), continue_target, breaks: vec![], continues: vec![] });
+        self.compile_block(body);
+        self.loops.pop().expect("vòng lặp vừa được thêm")
+    }
+
+    // `dung` / `tiep`: bỏ các biến cục bộ của thân vòng khỏi stack rồi nhảy; địa chỉ chưa biết thì để vá sau
+    fn compile_loop_jump(&mut self, label: Option<String>, span: Span, is_break: bool) {
+        let word = if is_break { "dung" } else { "tiep" };
+        let found = match &label {
+            None => self.loops.len().checked_sub(1),
+            Some(l) => self.loops.iter().rposition(|lp| lp.label.as_ref() == Some(l)),
+        };
+        let Some(idx) = found else {
+            match label {
+                None => self.error(span, &format!("{} chỉ dùng được trong vòng lặp", word)),
+                Some(l) => self.error(span, &format!("không có vòng lặp nào mang nhãn '{}", l)),
+            }
+            return;
+        };
+        for _ in self.loops[idx].locals..self.locals.len() { self.emit(9); }
+        self.emit(11);
+        match (is_break, self.loops[idx].continue_target) {
+            (false, Some(target)) => self.emit_u16(target),
+            _ => {
+                let at = self.chunk.code.len(); self.emit_u16(0);
+                if is_break { self.loops[idx].breaks.push(at); } else { self.loops[idx].continues.push(at); }
+            }
+        }
+    }
+
+    // Thiếu giá trị thì trả về rỗng; hàm có khai báo kiểu trả về thì kiểm tra trước khi rời khung gọi
+    fn compile_return(&mut self, value: Option<Expr>) {
+        match value {
+            Some(v) => self.compile_expr(v),
+            None => self.emit_constant(Value::Null),
+        }
+        if let Some((name, Some(ret))) = self.function.clone() {
+            self.check_type(&ret, format!("giá trị trả về của hàm '{}'", name));
+        }
+        self.emit(17);
+    }
+
+    // Lệnh 37: kiểu nằm trong `chunk.types`, mô tả chỗ nhận giá trị là một hằng chuỗi cho thông báo lỗi
+    fn check_type(&mut self, ty: &Type, target: String) {
+        let ty_idx = match self.chunk.types.iter().position(|t| t == ty) {
+            Some(idx) => idx,
+            None => { self.chunk.types.push(ty.clone()); self.chunk.types.len() - 1 }
+        };
+        let target_idx = self.add_constant(Value::Chuoi(target));
+        self.emit(37); self.emit_u16(ty_idx); self.emit_u16(target_idx);
+    }
+
+    fn compile_logic_branch(&mut self, expr: Option<Expr>, constant: bool) {
+        match expr {
+            Some(e) => { self.compile_expr(e); self.emit(36); }
+            None => self.emit_constant(Value::Logic(constant)),
+        }
+    }
+
+    // Khối `{ }` là một phạm vi: biến cục bộ khai báo trong đó bị bỏ khỏi stack khi ra khỏi khối
+    fn compile_block(&mut self, body: Vec<Stmt>) {
+        self.begin_scope();
+        for s in body { self.compile_stmt(s); }
+        self.end_scope();
+    }
+
+    fn begin_scope(&mut self) { self.scope_depth += 1; }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while matches!(self.locals.last(), Some(l) if l.depth > self.scope_depth) {
+            self.locals.pop();
+            self.emit(9);
+        }
+    }
+
+    // Giá trị trên đỉnh stack trở thành ô của biến cục bộ mới trong phạm vi hiện tại
+    fn add_local(&mut self, name: String) {
+        self.locals.push(Local { name, depth: self.scope_depth, ty: None });
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|l| l.name == name)
+    }
+
+    // Lấy giá trị trên đỉnh stack gán vào biến cục bộ nếu có, không thì vào biến toàn cục
+    fn set_variable(&mut self, name: &str) {
+        match self.resolve_local(name) {
+            Some(slot) => { self.emit(30); self.emit_u8(slot, "biến cục bộ"); }
+            None => {
+                let idx = self.get_or_create_name(name.to_string());
+                self.emit(6); self.emit_u16(idx);
+            }
+        }
+    }
+
+    // Quyết định có biên dịch phần thân của thuộc tính hay không
+    fn attribute_enabled(&mut self, attr: &Attr) -> bool {
+        match attr.name.as_str() {
+            "{}" | "ime" => true,
+            "end" => { self.warn(attr.span, "#[end] không đóng vùng nào"); false }
+            "bo_qua" | "ngon_ngu" => false,
+            "kiem_thu" => self.test_mode,
+            "neu_cau_hinh" => {
+                if attr.args.is_empty() { self.warn(attr.span, "#[neu_cau_hinh] cần ít nhất một cờ, ví dụ #[neu_cau_hinh(debug)]"); }
+                attr.args.iter().all(|flag| self.cfg.contains(flag))
+            }
+            other => {
+                self.warn(attr.span, &format!("thuộc tính không rõ #[{}], bỏ qua", other));
+                true
+            }
+        }
+    }
+
+    // Gọi hàm người dùng: giá trị hàm nằm dưới các đối số trên stack, lệnh 18 kèm số đối số
+    fn compile_call(&mut self, callee: String, args: Vec<Expr>) {
+        if self.resolve_local(&callee).is_none() {
+            match self.chunk.functions.iter().find(|f| f.name == callee) {
+                Some(f) if f.arity != args.len() => {
+                    let msg = format!("hàm '{}' cần {} tham số nhưng được gọi với {}", callee, f.arity, args.len());
+                    self.error(self.span, &msg);
+                }
+                None if !self.globals.iter().any(|(n, _)| *n == callee) => self.error(self.span, &format!("hàm '{}' chưa được khai báo", callee)),
+                _ => {}
+            }
+        }
+        let argc = args.len();
+        self.compile_expr(Expr::Variable(callee));
+        for arg in args { self.compile_expr(arg); }
+        self.emit(18); self.emit_u8(argc, "tham số");
+    }
+
+    // `NguoiChoi("An", 100, 50)`: giá trị các trường theo thứ tự khai báo, lệnh 51 gom lại thành bản ghi
+    fn compile_record(&mut self, idx: usize, args: Vec<Expr>) {
+        let record = self.chunk.records[idx].clone();
+        if args.len() != record.fields.len() {
+            self.error(self.span, &format!("kiểu '{}' có {} trường nhưng được tạo với {} giá trị", record.name, record.fields.len(), args.len()));
+        }
+        for arg in args { self.compile_expr(arg); }
+        self.emit(51); self.emit_u8(idx, "kiểu");
+    }
+
+    // `Chet` hoặc `BiThuong(30)`: lệnh 52 gom các giá trị mang theo cùng chỉ số loại và biến thể
+    fn compile_variant(&mut self, name: &str, args: Vec<Expr>, called: bool) {
+        let Some((e, v)) = self.variant(name) else { return };
+        let fields = self.chunk.enums[e].variants[v].fields.len();
+        if fields == 0 && called {
+            self.error(self.span, &format!("biến thể '{}' không mang giá trị, viết '{}' thay vì '{}(...)'", name, name, name));
+        } else if fields > 0 && !called {
+            self.error(self.span, &format!("biến thể '{}' cần {} giá trị, viết '{}(...)'", name, fields, name));
+        } else if args.len() != fields {
+            self.error(self.span, &format!("biến thể '{}' cần {} giá trị nhưng được tạo với {}", name, fields, args.len()));
+        }
+        for arg in args { self.compile_expr(arg); }
+        self.emit(52); self.emit_u8(e, "loại"); self.emit_u8(v, "biến thể");
+    }
+
+    // (chỉ số loại, chỉ số biến thể) của biến thể tên `name`
+    fn variant(&self, name: &str) -> Option<(usize, usize)> {
+        self.chunk.enums.iter().enumerate()
+            .find_map(|(e, en)| en.variants.iter().position(|v| v.name == name).map(|v| (e, v)))
+    }
+
+    // Tên đã dùng cho kiểu, loại hoặc biến thể thì không khai báo lại được
+    fn declared_type(&self, name: &str) -> Option<&'static str> {
+        if self.chunk.records.iter().any(|r| r.name == name) { Some("kiểu") }
+        else if self.chunk.enums.iter().any(|e| e.name == name) { Some("loại") }
+        else if self.variant(name).is_some() { Some("biến thể") }
+        else { None }
+    }
+
+    fn warn(&mut self, span: Span, msg: &str) { self.warnings.push(VlError::at(span, msg)); }
+    fn error(&mut self, span: Span, msg: &str) { self.errors.push(VlError::at(span, msg)); }
+
+    // Lỗi vượt giới hạn chỉ báo một lần, không lặp lại ở mọi lệnh phía sau
+    fn limit_error(&mut self, msg: &str) {
+        if !self.errors.iter().any(|e| e.msg_vi == msg) { self.error(self.span, msg); }
+    }
+
+    // Hằng giống hệt (số thực so theo bit để giữ -0.0) dùng chung một ô
+    fn add_constant(&mut self, val: Value) -> usize {
+        let same = |c: &Value| match (c, &val) { (Value::Thuc(a), Value::Thuc(b)) => a.to_bits() == b.to_bits(), _ => *c == val };
+        if let Some(idx) = self.chunk.constants.iter().position(same) { return idx; }
+        self.chunk.constants.push(val);
+        self.chunk.constants.len() - 1
+    }
+
+    fn emit_constant(&mut self, val: Value) {
+        let idx = self.add_constant(val);
+        self.emit(1); self.emit_u16(idx);
+    }
+
     fn get_or_create_name(&mut self, name: String) -> usize {
         if let Some(pos) = self.chunk.names.iter().position(|x| x == &name) { pos }
         else { self.chunk.names.push(name); self.chunk.names.len() - 1 }
@@ -1,75 +1,481 @@
-use crate::token::{Token, TokenType};
+use crate::token::{Dialect, Span, StrPiece, Token, TokenType};
+use crate::VlError;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
-pub struct Lexer { source: Vec<char>, current: usize }
+/// Lexer đọc thẳng trên `&str` theo byte và trả token dần dần qua `Iterator`,
+/// lexeme của token là lát cắt mượn từ nguồn nên không phải cấp phát lại.
+pub struct Lexer<'a> {
+    source: &'a str,
+    current: usize,
+    line: usize,
+    col: usize,
+    offset: usize,
+    start_span: Span,
+    errors: Vec<VlError>,
+    // Chỗ bắt đầu chuỗi hoặc chú thích khối chưa đóng; phần còn lại của file đã bị nuốt vào đó
+    unterminated: Option<Span>,
+    done: bool,
+    // Các ngoặc đang mở; khi ngoặc trong cùng là ( hoặc [ thì xuống dòng không kết thúc câu lệnh
+    brackets: Vec<char>,
+    after_newline: bool,
+    // Đã qua dấu xuống dòng kể từ token trước; `line_start` cho biết token vừa trả về có đứng đầu dòng không
+    saw_newline: bool,
+    line_start: bool,
+    dialect: Dialect,
+    // Dòng của `#[ngon_ngu(..)]` đầu file; nó luôn thắng lựa chọn truyền vào `with_dialect`
+    header_line: Option<usize>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let header = header(source);
+        Self {
+            source, current: 0, line: 1, col: 1, offset: 0, start_span: Span::default(), errors: vec![], unterminated: None, done: false,
+            brackets: vec![], after_newline: true, saw_newline: true, line_start: true, dialect: header.map(|h| h.1).unwrap_or_default(), header_line: header.map(|h| h.0),
+        }
+    }
+
+    /// Chọn bộ từ khoá, trừ khi file đã tự khai báo bằng `#[ngon_ngu(..)]`.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        if self.header_line.is_none() { self.dialect = dialect; }
+        self
+    }
+
+    pub fn dialect(&self) -> Dialect { self.dialect }
+
+    /// Dòng của `#[ngon_ngu(..)]` đã chọn bộ từ khoá cho file, nếu có.
+    pub fn header_line(&self) -> Option<usize> { self.header_line }
+
+    /// Bắt đầu đếm dòng/cột từ `span` thay vì 1:1, dùng khi quét lại đoạn mã nằm trong chuỗi nội suy.
+    pub fn starting_at(mut self, span: Span) -> Self {
+        self.line = span.line; self.col = span.col; self.offset = span.offset;
+        self
+    }
+
+    /// Quét toàn bộ file. Gặp lỗi thì ghi lại rồi quét tiếp, để một lần chạy báo đủ mọi lỗi.
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token<'a>>, Vec<VlError>> {
+        let tokens: Vec<Token<'a>> = self.by_ref().collect();
+        if self.errors.is_empty() { Ok(tokens) } else { Err(self.take_errors()) }
+    }
+
+    /// Lấy các lỗi đã gặp tính đến token vừa trả về.
+    pub fn take_errors(&mut self) -> Vec<VlError> { std::mem::take(&mut self.errors) }
+
+    pub fn unterminated(&self) -> Option<Span> { self.unterminated }
+
+    /// Token vừa quét có phải token đầu tiên trên dòng của nó không.
+    pub fn at_line_start(&self) -> bool { self.line_start }
+
+    /// Số ngoặc đang mở, để parser đóng bớt các ngoặc của một câu lệnh hỏng.
+    pub fn bracket_depth(&self) -> usize { self.brackets.len() }
+    /// Đóng các ( [ mở sau `depth`; `{` được giữ lại cho tới `}` của nó.
+    pub fn close_brackets(&mut self, depth: usize) {
+        let depth = depth.min(self.brackets.len());
+        let braces = self.brackets[depth..].iter().filter(|b| **b == '{').count();
+        self.brackets.truncate(depth);
+        self.brackets.extend(std::iter::repeat_n('{', braces));
+    }
 
-impl Lexer {
-    pub fn new(source: &str) -> Self {
-        Self { source: source.chars().collect(), current: 0 }
+    // Ngoặc đóng lạc loài không được đóng nhầm ngoặc khác loại đang mở
+    fn close_bracket(&mut self, open: char) {
+        if self.brackets.last() == Some(&open) { self.brackets.pop(); }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
-        let mut tokens = vec![];
+    fn scan_token(&mut self) -> Option<Token<'a>> {
         while !self.is_at_end() {
             let start = self.current;
+            self.start_span = self.span();
             let c = self.advance();
-            match c {
-                '(' => tokens.push(self.make_token(TokenType::LPAREN, start)),
-                ')' => tokens.push(self.make_token(TokenType::RPAREN, start)),
-                '{' => tokens.push(self.make_token(TokenType::LBRACE, start)),
-                '}' => tokens.push(self.make_token(TokenType::RBRACE, start)),
-                ',' => tokens.push(self.make_token(TokenType::COMMA, start)),
-                '=' => tokens.push(self.make_token(TokenType::BANG, start)),
-                '+' => tokens.push(self.make_token(TokenType::PLUS, start)),
-                '-' => tokens.push(self.make_token(TokenType::MINUS, start)),
-                '>' => tokens.push(self.make_token(TokenType::GT, start)),
-                '<' => tokens.push(self.make_token(TokenType::LT, start)),
-                '"' => tokens.push(self.string(start)),
-                ' ' | '\r' | '\t' | '\n' => {},
+            let token = match c {
+                '(' => { self.brackets.push(c); self.make_token(TokenType::LPAREN, start) }
+                ')' => { self.close_bracket('('); self.make_token(TokenType::RPAREN, start) }
+                '{' => { self.brackets.push(c); self.make_token(TokenType::LBRACE, start) }
+                '}' => {
+                    // `}` đóng luôn mọi ( [ còn dở trong khối
+                    while let Some(b) = self.brackets.pop() { if b == '{' { break; } }
+                    self.make_token(TokenType::RBRACE, start)
+                }
+                '[' => { self.brackets.push(c); self.make_token(TokenType::LBRACKET, start) }
+                ']' => { self.close_bracket('['); self.make_token(TokenType::RBRACKET, start) }
+                ';' => self.make_token(TokenType::SEMICOLON, start),
+                ',' => self.make_token(TokenType::COMMA, start),
+                ':' => self.make_token(TokenType::COLON, start),
+                '.' => self.make_token(TokenType::DOT, start),
+                '#' => self.make_token(TokenType::SHARP, start),
+                '+' => self.make_token(TokenType::PLUS, start),
+                '-' => { let k = if self.matches('>') { TokenType::ARROW } else { TokenType::MINUS }; self.make_token(k, start) }
+                '*' => { let k = if self.matches('*') { TokenType::STAR_STAR } else { TokenType::STAR }; self.make_token(k, start) }
+                '%' => self.make_token(TokenType::PERCENT, start),
+                // `//` đã là chú thích nên chia lấy phần nguyên dùng `\` như Visual Basic
+                '\\' => self.make_token(TokenType::BACKSLASH, start),
+                '/' if self.peek() == '/' => match self.line_comment(start) { Some(t) => t, None => continue },
+                '/' if self.peek() == '*' => { self.block_comment(); continue; }
+                '/' => self.make_token(TokenType::SLASH, start),
+                '=' => { let k = if self.matches('=') { TokenType::EQ_EQ } else { TokenType::ASSIGN }; self.make_token(k, start) }
+                '!' => { let k = if self.matches('=') { TokenType::BANG_EQ } else { TokenType::BANG }; self.make_token(k, start) }
+                '>' => { let k = if self.matches('=') { TokenType::GE } else { TokenType::GT }; self.make_token(k, start) }
+                '<' => { let k = if self.matches('=') { TokenType::LE } else { TokenType::LT }; self.make_token(k, start) }
+                '&' if self.matches('&') => self.make_token(TokenType::AND_AND, start),
+                '|' if self.matches('|') => self.make_token(TokenType::OR_OR, start),
+                '\'' if self.peek().is_alphabetic() || self.peek() == '_' => self.label(start),
+                '"' => match self.string(start) { Some(t) => t, None => continue },
+                // Nhiều dòng trống liền nhau chỉ cho ra một token NEWLINE
+                '\n' if !matches!(self.brackets.last(), Some('(' | '[')) && !self.after_newline => self.make_token(TokenType::NEWLINE, start),
+                '\n' => { self.saw_newline = true; continue; }
+                ' ' | '\r' | '\t' => continue,
                 _ => {
-                    if c.is_digit(10) { tokens.push(self.number(start)); }
-                    else if c.is_alphabetic() || c == '_' { tokens.push(self.identifier(start)); }
+                    if c.is_ascii_digit() { self.number(start) }
+                    else if c.is_alphabetic() || c == '_' { self.identifier(start) }
+                    else { self.error(&format!("ký tự không hợp lệ '{}'", c)); continue; }
                 }
-            }
+            };
+            self.after_newline = token.kind == TokenType::NEWLINE;
+            self.line_start = self.saw_newline;
+            self.saw_newline = self.after_newline;
+            return Some(token);
         }
-        tokens.push(Token { kind: TokenType::EOF, lexeme: "".into() });
-        tokens
+        None
+    }
+
+    // `// ...` bị bỏ qua, còn `/// ...` ở đầu dòng được giữ lại làm token DOC cho parser gắn vào khai báo kế tiếp;
+    // `///` nằm sau mã trên cùng dòng chỉ là chú thích thường
+    fn line_comment(&mut self, start: usize) -> Option<Token<'a>> {
+        self.advance();
+        let is_doc = self.peek() == '/' && self.after_newline;
+        if is_doc { self.advance(); }
+        let text_start = self.current;
+        while self.peek() != '\n' && !self.is_at_end() { self.advance(); }
+        if !is_doc { return None; }
+        let text = &self.source[text_start..self.current];
+        let text = text.strip_prefix(' ').unwrap_or(text).trim_end().to_string();
+        Some(self.make_token(TokenType::DOC(text), start))
     }
 
-    fn string(&mut self, start: usize) -> Token {
-        while self.peek() != '"' && !self.is_at_end() { self.advance(); }
+    // `/* ... */` được phép lồng nhau: `/* a /* b */ c */`
+    fn block_comment(&mut self) {
         self.advance();
-        let val: String = self.source[start + 1..self.current - 1].iter().collect();
-        Token { kind: TokenType::CHUOI(val.clone()), lexeme: val }
-    }
-
-    fn number(&mut self, start: usize) -> Token {
-        while self.peek().is_digit(10) || self.peek() == '.' { self.advance(); }
-        let s: String = self.source[start..self.current].iter().collect();
-        Token { kind: TokenType::Int(s.parse().unwrap_or(0.0)), lexeme: s }
-    }
-
-    fn identifier(&mut self, start: usize) -> Token {
-        while self.peek().is_alphanumeric() || self.peek() == '_' { self.advance(); }
-        let text: String = self.source[start..self.current].iter().collect();
-        let kind = match text.as_str() {
-            "tb" => TokenType::TB,
-            "lap" => TokenType::LAP,
-            "in" | "in_dong" => TokenType::IN,
-            "neu" => TokenType::NEU,
-            "nguoc_lai" => TokenType::NGUOC_LAI,
-            "ngu" => TokenType::TH,
-            "ngaunhien" => TokenType::NGAUNHIEN,
-            "nhap" | "doc_file" => TokenType::DOC_FILE,
-            _ => TokenType::TEN(text.clone()),
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error("chú thích khối chưa được đóng, thiếu */");
+                self.unterminated.get_or_insert(self.start_span);
+                return;
+            }
+            match self.advance() {
+                '/' if self.peek() == '*' => { self.advance(); depth += 1; }
+                '*' if self.peek() == '/' => { self.advance(); depth -= 1; }
+                _ => {}
+            }
+        }
+    }
+
+    fn string(&mut self, start: usize) -> Option<Token<'a>> {
+        let triple = self.peek() == '"' && self.peek_next() == '"';
+        if triple { self.advance(); self.advance(); }
+        let body_start = self.current;
+        if !self.skip_string_body(triple) {
+            if triple { self.error("chuỗi nhiều dòng chưa được đóng, thiếu \"\"\""); }
+            else { self.error("chuỗi chưa được đóng, thiếu dấu \""); }
+            self.unterminated.get_or_insert(self.start_span);
+            return None;
+        }
+        let raw = &self.source[body_start..self.current];
+        let quotes = if triple { 3 } else { 1 };
+        for _ in 0..quotes { self.advance(); }
+        // Chuỗi nhiều dòng """...""": bỏ dòng trống đầu/cuối và phần thụt lề chung của mọi dòng
+        let pieces = if triple { self.split_pieces(&dedent(raw), start, None) } else { self.split_pieces(raw, start, Some(body_start)) };
+        let kind = match <[StrPiece; 1]>::try_from(pieces) {
+            Ok([StrPiece::Lit(s)]) => TokenType::CHUOI(s),
+            Ok(one) => TokenType::CHUOI_MAU(one.into()),
+            Err(pieces) => TokenType::CHUOI_MAU(pieces),
         };
-        Token { kind, lexeme: text }
+        Some(self.make_token(kind, start))
+    }
+
+    // Tìm dấu đóng chuỗi, bỏ qua ký tự thoát và các chuỗi con nằm trong `{...}`
+    fn skip_string_body(&mut self, triple: bool) -> bool {
+        loop {
+            if self.is_at_end() { return false; }
+            match self.peek() {
+                '\\' => { self.advance(); if !self.is_at_end() { self.advance(); } }
+                '{' if self.peek_next() == '{' => { self.advance(); self.advance(); }
+                '{' => {
+                    self.advance();
+                    let mut depth = 1;
+                    while depth > 0 && !self.is_at_end() {
+                        match self.advance() {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            '"' => {
+                                while self.peek() != '"' && !self.is_at_end() {
+                                    if self.peek() == '\\' { self.advance(); }
+                                    if !self.is_at_end() { self.advance(); }
+                                }
+                                if !self.is_at_end() { self.advance(); }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                '"' if !triple || (self.peek_next() == '"' && self.peek_at(2) == '"') => return true,
+                _ => { self.advance(); }
+            }
+        }
+    }
+
+    // Tách chuỗi thành các mảnh chữ và mã `{bieu_thuc:dinh_dang}`; `{{` và `}}` là dấu ngoặc thường.
+    // `base` là vị trí của `raw` trong file, để mảnh mã có đúng dòng/cột (chuỗi nhiều dòng thì dùng vị trí token).
+    fn split_pieces(&mut self, raw: &str, start: usize, base: Option<usize>) -> Vec<StrPiece> {
+        let (idx, chars): (Vec<usize>, Vec<char>) = raw.char_indices().unzip();
+        let mut pieces = vec![];
+        let mut lit = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            match c {
+                // `\u{..}` là mã ký tự, không phải chỗ nội suy
+                '\\' if next == Some('u') && chars.get(i + 2) == Some(&'{') => {
+                    let end = chars[i..].iter().position(|&c| c == '}').map_or(chars.len(), |p| i + p + 1);
+                    lit.extend(&chars[i..end]);
+                    i = end;
+                }
+                '\\' => { lit.push(c); lit.extend(next); i += 2; }
+                '{' if next == Some('{') => { lit.push('{'); i += 2; }
+                '}' if next == Some('}') => { lit.push('}'); i += 2; }
+                '}' => { self.error("dấu } lẻ trong chuỗi, viết }} nếu muốn in dấu }"); i += 1; }
+                '{' => {
+                    let end = matching_brace(&chars, i);
+                    let code = &chars[i + 1..end];
+                    let (src, spec) = match top_level_colon(code) {
+                        Some(k) => (code[..k].iter().collect::<String>(), Some(code[k + 1..].iter().collect::<String>())),
+                        None => (code.iter().collect(), None),
+                    };
+                    let span = match base { Some(b) => self.span_at(start, b + idx[i] + 1), None => self.start_span };
+                    if !lit.is_empty() { let text = self.unescape(&std::mem::take(&mut lit)); pieces.push(StrPiece::Lit(text)); }
+                    pieces.push(StrPiece::Code { src, spec, span });
+                    i = end + 1;
+                }
+                _ => { lit.push(c); i += 1; }
+            }
+        }
+        if !lit.is_empty() || pieces.is_empty() { let text = self.unescape(&lit); pieces.push(StrPiece::Lit(text)); }
+        pieces
+    }
+
+    // Tính dòng/cột của ký tự thứ `idx` trong file, đếm tiếp từ đầu token đang quét
+    fn span_at(&self, start: usize, idx: usize) -> Span {
+        let mut span = self.start_span;
+        for c in self.source[start..idx].chars() {
+            span.offset += c.len_utf8();
+            if c == '\n' { span.line += 1; span.col = 1; } else { span.col += 1; }
+        }
+        span
     }
 
-    fn advance(&mut self) -> char { self.current += 1; self.source[self.current - 1] }
-    fn peek(&self) -> char { if self.is_at_end() { '\0' } else { self.source[self.current] } }
+    fn unescape(&mut self, raw: &str) -> String {
+        let mut out = String::new();
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' { out.push(c); continue; }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('u') => {
+                    let rest = chars.as_str();
+                    let hex = rest.strip_prefix('{').and_then(|r| r.split_once('}')).map(|(h, _)| h);
+                    match hex.and_then(|h| u32::from_str_radix(h, 16).ok()).and_then(char::from_u32) {
+                        Some(ch) => {
+                            out.push(ch);
+                            chars = rest[hex.unwrap_or("").len() + 2..].chars();
+                        }
+                        None => self.error("mã \\u không hợp lệ, cần dạng \\u{1EA1}"),
+                    }
+                }
+                Some(other) => self.error(&format!("ký tự thoát không hợp lệ '\\{}'", other)),
+                None => self.error("chuỗi kết thúc bằng dấu \\ lẻ"),
+            }
+        }
+        out
+    }
+
+    // Số nguyên: 42, 1_000_000, 0xFF, 0b1010. Số thực: 3.14, 6.02e23, 1e-3.
+    fn number(&mut self, start: usize) -> Token<'a> {
+        let radix = match (self.source.as_bytes()[start], self.peek()) {
+            (b'0', 'x' | 'X') => 16,
+            (b'0', 'b' | 'B') => 2,
+            _ => 10,
+        };
+        let mut is_real = false;
+        if radix != 10 {
+            self.advance();
+            while self.peek().is_digit(radix) || self.peek() == '_' { self.advance(); }
+        } else {
+            self.digits();
+            if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+                is_real = true;
+                self.advance();
+                self.digits();
+            }
+            if matches!(self.peek(), 'e' | 'E') && (self.peek_next().is_ascii_digit() || (matches!(self.peek_next(), '+' | '-') && self.peek_at(2).is_ascii_digit())) {
+                is_real = true;
+                self.advance();
+                if matches!(self.peek(), '+' | '-') { self.advance(); }
+                self.digits();
+            }
+        }
+        // Phần dính liền phía sau như `1.2.3` hay `12abc` làm cả cụm thành số sai
+        let mut malformed = false;
+        while self.peek().is_alphanumeric() || self.peek() == '_' || (self.peek() == '.' && self.peek_next().is_ascii_digit()) {
+            malformed = true;
+            self.advance();
+        }
+        let lexeme = &self.source[start..self.current];
+        let digits: String = lexeme.chars().skip(if radix == 10 { 0 } else { 2 }).filter(|c| *c != '_').collect();
+        let kind = if malformed || digits.is_empty() || lexeme.ends_with('_') || lexeme.contains("__") {
+            None
+        } else if is_real {
+            digits.parse().ok().map(TokenType::Float)
+        } else {
+            match i64::from_str_radix(&digits, radix) {
+                Ok(n) => Some(TokenType::Int(n)),
+                Err(_) => {
+                    self.error(&format!("số '{}' quá lớn cho kiểu nguyên", lexeme));
+                    Some(TokenType::Int(0))
+                }
+            }
+        };
+        let kind = kind.unwrap_or_else(|| {
+            self.error(&format!("số không hợp lệ '{}'", lexeme));
+            TokenType::Int(0)
+        });
+        self.make_token(kind, start)
+    }
+
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit() || self.peek() == '_' { self.advance(); }
+    }
+
+    // Tên được chuẩn hoá NFC, để `máu` gõ bằng bộ gõ dựng sẵn hay tổ hợp đều là cùng một biến
+    fn identifier(&mut self, start: usize) -> Token<'a> {
+        while self.peek().is_alphanumeric() || self.peek() == '_' || is_combining_mark(self.peek()) { self.advance(); }
+        let lexeme = &self.source[start..self.current];
+        let normalized: String;
+        let text = if lexeme.is_ascii() || is_nfc(lexeme) { lexeme } else { normalized = lexeme.nfc().collect(); &normalized };
+        let kind = self.dialect.keyword(text).unwrap_or_else(|| TokenType::TEN(text.to_string()));
+        self.make_token(kind, start)
+    }
+
+    // Nhãn vòng lặp `'ngoai`, dùng với `dung 'ngoai` / `tiep 'ngoai`
+    fn label(&mut self, start: usize) -> Token<'a> {
+        while self.peek().is_alphanumeric() || self.peek() == '_' || is_combining_mark(self.peek()) { self.advance(); }
+        let name = &self.source[start + 1..self.current];
+        let name = if name.is_ascii() || is_nfc(name) { name.to_string() } else { name.nfc().collect() };
+        self.make_token(TokenType::LABEL(name), start)
+    }
+
+    fn advance(&mut self) -> char {
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.offset += c.len_utf8();
+        if c == '\n' { self.line += 1; self.col = 1; } else { self.col += 1; }
+        c
+    }
+    fn matches(&mut self, expected: char) -> bool {
+        if self.peek() != expected { return false; }
+        self.advance();
+        true
+    }
+    fn peek(&self) -> char {
+        match self.source.as_bytes().get(self.current) {
+            Some(&b) if b.is_ascii() => b as char,
+            Some(_) => self.source[self.current..].chars().next().unwrap_or('\0'),
+            None => '\0',
+        }
+    }
+    fn peek_next(&self) -> char { self.peek_at(1) }
+    fn peek_at(&self, n: usize) -> char { self.source[self.current..].chars().nth(n).unwrap_or('\0') }
     fn is_at_end(&self) -> bool { self.current >= self.source.len() }
-    fn make_token(&self, kind: TokenType, start: usize) -> Token {
-        let lexeme: String = self.source[start..self.current].iter().collect();
-        Token { kind, lexeme }
+    fn error(&mut self, msg: &str) { self.errors.push(VlError::at(self.start_span, msg)); }
+    fn span(&self) -> Span { Span { line: self.line, col: self.col, offset: self.offset } }
+    fn make_token(&self, kind: TokenType, start: usize) -> Token<'a> {
+        Token { kind, lexeme: &self.source[start..self.current], span: self.start_span }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    // Hết nguồn thì trả đúng một token EOF rồi dừng
+    fn next(&mut self) -> Option<Token<'a>> {
+        if self.done { return None; }
+        let token = self.scan_token();
+        if token.is_some() { return token; }
+        self.done = true;
+        Some(Token { kind: TokenType::EOF, lexeme: "", span: self.span() })
+    }
+}
+
+/// Đọc `#[ngon_ngu(en)]` nếu nó là dòng có nội dung đầu tiên của file (bỏ qua dòng trống và chú thích `//`).
+pub fn header_dialect(source: &str) -> Option<Dialect> { header(source).map(|h| h.1) }
+
+fn header(source: &str) -> Option<(usize, Dialect)> {
+    let (i, line) = source.lines().map(str::trim).enumerate().find(|(_, l)| !l.is_empty() && !l.starts_with("//"))?;
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    let name = compact.strip_prefix("#[ngon_ngu(")?.strip_suffix(")]")?;
+    Some((i + 1, Dialect::from_name(name)?))
+}
+
+fn dedent(raw: &str) -> String {
+    let mut lines: Vec<&str> = raw.split('\n').collect();
+    if lines.first().is_some_and(|l| l.trim().is_empty()) { lines.remove(0); }
+    if lines.last().is_some_and(|l| l.trim().is_empty()) { lines.pop(); }
+    let indent = lines.iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines.iter()
+        .map(|l| l.get(indent..).unwrap_or("").trim_end_matches('\r'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Vị trí dấu `}` khớp với `{` tại `open`, bỏ qua ngoặc nằm trong chuỗi con
+fn matching_brace(chars: &[char], open: usize) -> usize {
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if in_str => i += 1,
+            '"' => in_str = !in_str,
+            '{' if !in_str => depth += 1,
+            '}' if !in_str => { depth -= 1; if depth == 0 { return i; } }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+// Dấu `:` tách biểu thức với định dạng, chỉ tính khi không nằm trong ngoặc hay chuỗi con
+fn top_level_colon(code: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_str = false;
+    for (i, &c) in code.iter().enumerate() {
+        match c {
+            '"' => in_str = !in_str,
+            '(' | '[' | '{' if !in_str => depth += 1,
+            ')' | ']' | '}' if !in_str => depth -= 1,
+            ':' if !in_str && depth == 0 => return Some(i),
+            _ => {}
+        }
     }
+    None
 }
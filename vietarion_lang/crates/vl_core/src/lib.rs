@@ -1,8 +1,11 @@
+use std::fmt;
+
 pub mod token;
 pub mod lexer;
 pub mod ast;
 pub mod compiler;
 pub mod parser;
+pub mod translate;
 
 #[derive(Debug, Clone)]
 pub struct VlError {
@@ -10,4 +13,25 @@ pub struct VlError {
     pub msg_en: String,
     pub line: usize,
     pub col: usize,
+    /// Các token parser chờ đợi tại vị trí lỗi, rỗng nếu lỗi không phải do thiếu token
+    pub expected: Vec<String>,
+}
+
+impl VlError {
+    pub fn at(span: token::Span, msg_vi: &str) -> Self {
+        Self { msg_vi: msg_vi.into(), msg_en: "".into(), line: span.line, col: span.col, expected: vec![] }
+    }
+
+    /// Lỗi dạng "mong đợi ')' hoặc ',' nhưng gặp 'tb'".
+    pub fn expected(span: token::Span, expected: &[&str], found: &str) -> Self {
+        let msg_vi = format!("mong đợi {} nhưng gặp {}", expected.join(" hoặc "), found);
+        let expected = expected.iter().map(|s| s.to_string()).collect();
+        Self { expected, ..Self::at(span, &msg_vi) }
+    }
+}
+
+impl fmt::Display for VlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[dòng {}, cột {}] {}", self.line, self.col, self.msg_vi)
+    }
 }
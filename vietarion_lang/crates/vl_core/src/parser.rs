@@ -1,148 +1,739 @@
-use crate::ast::{Stmt, Expr};
-use crate::token::{Token, TokenType};
+use crate::ast::{Attr, Stmt, Expr, InterpPart};
+use crate::lexer::Lexer;
+use crate::token::{Dialect, StrPiece, Token, TokenType};
+use crate::VlError;
+use vl_vm::Type;
 
-#[derive(Debug)]
-pub struct VlError { pub msg_vi: String, pub msg_en: String, pub line: usize, pub col: usize }
-
-pub struct Parser { tokens: Vec<Token>, current: usize }
+/// Parser kéo token từ lexer khi cần, chỉ giữ đúng một token nhìn trước.
+/// Gặp lỗi thì ghi lại rồi bỏ qua tới đầu câu lệnh kế tiếp để báo được mọi lỗi trong một lần chạy.
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    current: Token<'a>,
+    errors: Vec<VlError>,
+    // Dấu xuống dòng kết thúc câu lệnh đã bị nuốt khi nhìn xem có `nguoc_lai` phía sau không
+    terminated: bool,
+    // Số `{` đã đi qua mà chưa gặp `}` tương ứng, để bước đồng bộ biết câu lệnh hỏng còn mở bao nhiêu khối
+    braces: usize,
+    pub warnings: Vec<VlError>,
+}
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self { Self { tokens, current: 0 } }
+impl<'a> Parser<'a> {
+    pub fn new(mut lexer: Lexer<'a>) -> Self {
+        let current = lexer.next().unwrap_or(Token { kind: TokenType::EOF, lexeme: "", span: Default::default() });
+        Self { lexer, current, errors: vec![], terminated: false, braces: 0, warnings: vec![] }
+    }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, VlError> {
+    /// Lỗi cú pháp nằm trên cùng dòng với lỗi của lexer bị bỏ, vì thường chỉ là hệ quả của lỗi đó;
+    /// sau một chuỗi hay chú thích chưa đóng thì mọi lỗi cú pháp phía sau đều bị bỏ.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<VlError>> {
         let mut stmts = vec![];
-        while !self.is_at_end() {
-            if let Some(stmt) = self.parse_stmt()? { stmts.push(stmt); }
+        loop {
+            self.skip_terminators();
+            if self.is_at_end() { break; }
+            stmts.extend(self.statement());
+        }
+        let mut errors = self.lexer.take_errors();
+        let lexer_lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        let cut = self.lexer.unterminated().map_or((usize::MAX, 0), |s| (s.line, s.col));
+        errors.extend(self.errors.drain(..).filter(|e| !lexer_lines.contains(&e.line) && (e.line, e.col) < cut));
+        errors.sort_by_key(|e| (e.line, e.col));
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
+    }
+
+    // Một câu lệnh cùng dấu kết thúc; lỗi được ghi lại thay vì dừng cả quá trình phân tích
+    fn statement(&mut self) -> Option<Stmt> {
+        let start = self.peek().span.offset;
+        let braces = self.braces;
+        // Token nhìn trước đã được quét nên ngoặc của nó (nếu có) đã nằm trong lexer
+        let depth = self.lexer.bracket_depth().saturating_sub(matches!(self.peek().kind, TokenType::LPAREN | TokenType::LBRACKET | TokenType::LBRACE) as usize);
+        match self.parse_stmt().and_then(|s| { self.end_of_stmt()?; Ok(s) }) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                self.errors.push(e);
+                self.terminated = false;
+                // Ngoặc ( [ bỏ dở không được nuốt mất các dấu xuống dòng phía sau;
+                // `{` câu lệnh đã mở (như của bảng còn dở) thì bỏ qua luôn tới `}` của nó
+                self.lexer.close_brackets(depth);
+                if self.peek().span.offset == start { self.advance(); }
+                self.synchronize(self.braces.saturating_sub(braces));
+                None
+            }
+        }
+    }
+
+    // Bỏ token tới hết dòng, tới từ khoá mở đầu câu lệnh ở đầu dòng hoặc tới `}` của khối đang mở.
+    // Từ khoá nằm giữa dòng thường thuộc ngoặc bỏ dở như `in(2 tb)` nên không dừng ở đó.
+    // Khối `{ }` nằm trong câu lệnh hỏng bị bỏ trọn để `}` của nó không gây thêm lỗi;
+    // `depth` là số `{` câu lệnh hỏng đã mở mà chưa đóng.
+    fn synchronize(&mut self, open: usize) {
+        let mut depth = open;
+        loop {
+            match self.peek().kind {
+                TokenType::EOF => return,
+                TokenType::RBRACE if depth == 0 => return,
+                TokenType::NEWLINE | TokenType::SEMICOLON if depth == 0 => { self.advance(); return; }
+                TokenType::TB | TokenType::NEU | TokenType::LAP | TokenType::CHO | TokenType::MOI | TokenType::HAM
+                | TokenType::TRA_VE | TokenType::DUNG | TokenType::TIEP | TokenType::KIEU | TokenType::LOAI | TokenType::SHARP if depth == 0 && self.lexer.at_line_start() => return,
+                TokenType::LBRACE => depth += 1,
+                TokenType::RBRACE => depth -= 1,
+                _ => {}
+            }
+            self.advance();
         }
-        Ok(stmts)
     }
 
     fn parse_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let doc = self.doc_comment();
         match self.peek().kind {
-            TokenType::TB => self.var_decl(),
+            TokenType::TB => self.var_decl(doc),
+            TokenType::RBRACE if doc.is_some() => Ok(None),
             TokenType::NEU => self.if_stmt(),
             TokenType::LAP => self.while_stmt(),
+            TokenType::CHO => self.for_stmt(),
+            TokenType::MOI => self.for_each_stmt(),
+            TokenType::HAM => self.function(doc),
+            TokenType::KIEU => self.record(doc),
+            TokenType::LOAI => self.enum_decl(doc),
+            TokenType::TRA_VE => self.return_stmt(),
+            TokenType::LABEL(_) => self.labeled_loop(),
+            TokenType::DUNG | TokenType::TIEP => self.jump_stmt(),
+            TokenType::SHARP => self.attribute(),
             TokenType::EOF => { self.advance(); Ok(None) },
             _ => self.expression_stmt(),
         }
     }
 
-    fn var_decl(&mut self) -> Result<Option<Stmt>, VlError> {
-        self.advance(); // skip 'tb'
-        let name = if let TokenType::TEN(ref n) = self.advance().kind { n.clone() } else { return Err(self.error("Thieu ten bien")); };
-        if let TokenType::BANG = self.peek().kind { self.advance(); }
+    // Gom các dòng `///` liền nhau thành một chuỗi tài liệu
+    fn doc_comment(&mut self) -> Option<String> {
+        let mut lines = vec![];
+        while matches!(self.peek().kind, TokenType::DOC(_)) {
+            if let TokenType::DOC(text) = self.advance().kind { lines.push(text); }
+            self.skip_newlines();
+        }
+        if lines.is_empty() { None } else { Some(lines.join("\n")) }
+    }
+
+    fn attribute(&mut self) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip '#'
+        self.consume(TokenType::LBRACKET, "'['")?;
+        // `#[{ ... }]` bọc sẵn một khối; `#[{}]` rỗng thì mở một khối kéo dài tới `#[end]`
+        if let TokenType::LBRACE = self.peek().kind {
+            self.advance();
+            let attr = Attr { name: "{}".into(), args: vec![], span };
+            let body = if let TokenType::RBRACE = self.peek().kind {
+                self.advance();
+                self.consume(TokenType::RBRACKET, "']'")?;
+                self.region(&attr)?
+            } else {
+                let body = self.block_body()?;
+                self.consume(TokenType::RBRACKET, "']'")?;
+                body
+            };
+            return Ok(Some(Stmt::Attribute { attr, body, span }));
+        }
+        let name = self.identifier("tên thuộc tính")?;
+        let mut args = vec![];
+        if let TokenType::LPAREN = self.peek().kind {
+            self.advance();
+            while !matches!(self.peek().kind, TokenType::RPAREN) {
+                match &self.peek().kind {
+                    TokenType::TEN(s) | TokenType::CHUOI(s) => args.push(s.clone()),
+                    TokenType::Int(_) | TokenType::Float(_) => args.push(self.peek().lexeme.to_string()),
+                    _ => return Err(self.expect(&["tên", "số", "chuỗi"])),
+                }
+                self.advance();
+                if !self.list_separator()? { break; }
+            }
+            self.consume(TokenType::RPAREN, "')'")?;
+        }
+        self.consume(TokenType::RBRACKET, "']'")?;
+        let attr = Attr { name, args, span };
+        if attr.name == "ngon_ngu" && self.lexer.header_line() != Some(span.line) {
+            let msg = match attr.args.first() {
+                Some(arg) if Dialect::from_name(arg).is_none() => format!("không có bộ từ khoá '{}', chỉ có 'vi' hoặc 'en'", arg),
+                _ => "#[ngon_ngu(..)] chỉ có tác dụng ở dòng có nội dung đầu tiên của file nên bị bỏ qua".into(),
+            };
+            self.warnings.push(VlError::at(span, &msg));
+        }
+        let body = match attr.name.as_str() {
+            "end" | "ngon_ngu" => vec![],
+            "ime" => self.region(&attr)?,
+            _ => { self.skip_newlines(); self.parse_stmt()?.into_iter().collect() }
+        };
+        Ok(Some(Stmt::Attribute { attr, body, span }))
+    }
+
+    // Vùng `#[ime] ... #[end]`: gom mọi câu lệnh cho tới `#[end]`
+    fn region(&mut self, attr: &Attr) -> Result<Vec<Stmt>, VlError> {
+        let mut body = vec![];
+        loop {
+            self.skip_terminators();
+            if self.is_at_end() {
+                return Err(VlError::at(attr.span, &format!("thiếu #[end] cho #[{}]", attr.name)));
+            }
+            match self.statement() {
+                // `#[end]` đã nhận dấu kết thúc của chính nó
+                Some(Stmt::Attribute { attr, .. }) if attr.name == "end" => { self.terminated = true; return Ok(body); }
+                Some(s) => body.push(s),
+                None => {}
+            }
+        }
+    }
+
+    fn var_decl(&mut self, doc: Option<String>) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'tb'
+        let name = self.identifier("tên biến")?;
+        let ty = self.annotation()?;
+        self.consume(TokenType::ASSIGN, "'='")?;
         let init = self.parse_expr()?;
-        Ok(Some(Stmt::VarDecl { name, init }))
+        Ok(Some(Stmt::VarDecl { name, ty, init, doc, span }))
+    }
+
+    // `ham ten(a, b) { ... }`
+    fn function(&mut self, doc: Option<String>) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'ham'
+        let name = self.identifier("tên hàm")?;
+        self.consume(TokenType::LPAREN, "'('")?;
+        let mut params: Vec<(String, Option<Type>)> = vec![];
+        if !matches!(self.peek().kind, TokenType::RPAREN) {
+            loop {
+                let param_span = self.peek().span;
+                let param = self.identifier("tên tham số")?;
+                if params.iter().any(|(p, _)| *p == param) {
+                    return Err(VlError::at(param_span, &format!("tham số '{}' bị trùng", param)));
+                }
+                let ty = self.annotation()?;
+                params.push((param, ty));
+                if !self.list_separator()? { break; }
+            }
+        }
+        self.consume(TokenType::RPAREN, "')'")?;
+        let ret = if let TokenType::ARROW = self.peek().kind { self.advance(); Some(self.type_ann()?) } else { None };
+        let body = self.block()?;
+        Ok(Some(Stmt::Function { name, params, ret, body, doc, span }))
+    }
+
+    // `kieu Ten { a, b }`, các trường cách nhau bằng dấu phẩy hoặc xuống dòng
+    fn record(&mut self, doc: Option<String>) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'kieu'
+        let name = self.identifier("tên kiểu")?;
+        self.consume(TokenType::LBRACE, "'{'")?;
+        let mut fields: Vec<String> = vec![];
+        let mut duplicate = None;
+        loop {
+            self.skip_newlines();
+            if let TokenType::RBRACE = self.peek().kind { break; }
+            let field_span = self.peek().span;
+            let field = self.identifier("tên trường")?;
+            if fields.contains(&field) && duplicate.is_none() { duplicate = Some((field_span, field.clone())); }
+            fields.push(field);
+            match self.peek().kind {
+                TokenType::COMMA => { self.advance(); }
+                TokenType::NEWLINE => {}
+                TokenType::RBRACE => break,
+                _ => return Err(self.expect(&["'}'", "','"])),
+            }
+        }
+        self.advance();
+        // Báo trùng sau khi đã qua `}` để bước đồng bộ không dừng ở giữa khai báo
+        if let Some((field_span, field)) = duplicate {
+            return Err(VlError::at(field_span, &format!("trường '{}' bị trùng", field)));
+        }
+        Ok(Some(Stmt::Record { name, fields, doc, span }))
+    }
+
+    // `loai Ten { A, B(x, y) }`, các biến thể cách nhau bằng dấu phẩy hoặc xuống dòng
+    fn enum_decl(&mut self, doc: Option<String>) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'loai'
+        let name = self.identifier("tên loại")?;
+        self.consume(TokenType::LBRACE, "'{'")?;
+        let mut variants: Vec<(String, Vec<String>)> = vec![];
+        let mut duplicate = None;
+        loop {
+            self.skip_newlines();
+            if let TokenType::RBRACE = self.peek().kind { break; }
+            let variant_span = self.peek().span;
+            let variant = self.identifier("tên biến thể")?;
+            let mut fields: Vec<String> = vec![];
+            if let TokenType::LPAREN = self.peek().kind {
+                self.advance();
+                while !matches!(self.peek().kind, TokenType::RPAREN) {
+                    let field_span = self.peek().span;
+                    let field = self.identifier("tên giá trị")?;
+                    if fields.contains(&field) && duplicate.is_none() { duplicate = Some((field_span, format!("giá trị '{}' của '{}' bị trùng", field, variant))); }
+                    fields.push(field);
+                    if !self.list_separator()? { break; }
+                }
+                self.consume(TokenType::RPAREN, "')'")?;
+            }
+            if variants.iter().any(|(v, _)| *v == variant) && duplicate.is_none() { duplicate = Some((variant_span, format!("biến thể '{}' bị trùng", variant))); }
+            variants.push((variant, fields));
+            match self.peek().kind {
+                TokenType::COMMA => { self.advance(); }
+                TokenType::NEWLINE => {}
+                TokenType::RBRACE => break,
+                _ => return Err(self.expect(&["'}'", "','"])),
+            }
+        }
+        self.advance();
+        if let Some((dup_span, msg)) = duplicate { return Err(VlError::at(dup_span, &msg)); }
+        Ok(Some(Stmt::Enum { name, variants, doc, span }))
+    }
+
+    // `: kiểu` tuỳ chọn sau tên biến hoặc tên tham số
+    fn annotation(&mut self) -> Result<Option<Type>, VlError> {
+        if let TokenType::COLON = self.peek().kind {
+            self.advance();
+            return Ok(Some(self.type_ann()?));
+        }
+        Ok(None)
+    }
+
+    // nguyen | thuc | logic | chuoi | [kiểu] | ham(kiểu, ...) -> kiểu
+    fn type_ann(&mut self) -> Result<Type, VlError> {
+        match self.peek().kind {
+            TokenType::LBRACKET => {
+                self.advance();
+                let item = self.type_ann()?;
+                self.consume(TokenType::RBRACKET, "']'")?;
+                Ok(Type::List(Box::new(item)))
+            }
+            TokenType::HAM => {
+                self.advance();
+                self.consume(TokenType::LPAREN, "'('")?;
+                let mut params = vec![];
+                if !matches!(self.peek().kind, TokenType::RPAREN) {
+                    loop {
+                        params.push(self.type_ann()?);
+                        if !self.list_separator()? { break; }
+                    }
+                }
+                self.consume(TokenType::RPAREN, "')'")?;
+                let ret = if let TokenType::ARROW = self.peek().kind { self.advance(); Some(Box::new(self.type_ann()?)) } else { None };
+                Ok(Type::Function { params, ret })
+            }
+            _ => {
+                let span = self.peek().span;
+                let name = self.identifier("kiểu")?;
+                Type::from_name(&name).ok_or_else(|| VlError::at(span, &format!("kiểu không rõ '{}', mong đợi nguyen, thuc, logic, chuoi, [kiểu] hoặc ham(...)", name)))
+            }
+        }
+    }
+
+    // `tra_ve` không kèm biểu thức thì trả về giá trị rỗng
+    fn return_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'tra_ve'
+        let value = match self.peek().kind {
+            TokenType::NEWLINE | TokenType::SEMICOLON | TokenType::RBRACE | TokenType::EOF => None,
+            _ => Some(self.parse_expr()?),
+        };
+        Ok(Some(Stmt::Return { value, span }))
     }
 
     fn expression_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let span = self.peek().span;
         let expr = self.parse_expr()?;
-        Ok(Some(Stmt::Expression(expr)))
+        if let TokenType::ASSIGN = self.peek().kind {
+            let eq = self.advance();
+            let value = self.parse_expr()?;
+            return match expr {
+                Expr::Variable(name) => Ok(Some(Stmt::Assign { name, value, span })),
+                Expr::Index { object, index } => Ok(Some(Stmt::IndexAssign { object: *object, index: *index, value, span })),
+                _ => Err(VlError::at(eq.span, "chỉ gán được cho tên biến, phần tử ds[i] hoặc m.ten")),
+            };
+        }
+        Ok(Some(Stmt::Expression { expr, span }))
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, VlError> { self.or() }
+
+    fn or(&mut self) -> Result<Expr, VlError> {
+        let mut expr = self.and()?;
+        while let TokenType::OR_OR = self.peek().kind {
+            let op = self.advance().kind;
+            let right = self.and()?;
+            expr = Expr::Logical { left: Box::new(expr), op, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, VlError> {
+        let mut expr = self.not()?;
+        while let TokenType::AND_AND = self.peek().kind {
+            let op = self.advance().kind;
+            let right = self.not()?;
+            expr = Expr::Logical { left: Box::new(expr), op, right: Box::new(right) };
+        }
+        Ok(expr)
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, VlError> { self.equality() }
+    // Từ `khong` lỏng hơn phép so sánh như `not` của Python: `khong a == b` là `khong (a == b)`.
+    // Ký hiệu `!` vẫn là phép một ngôi chặt ở `unary`.
+    fn not(&mut self) -> Result<Expr, VlError> {
+        if self.is_word_not() {
+            let op = self.advance().kind;
+            let right = self.not()?;
+            return Ok(Expr::Unary { op, right: Box::new(right) });
+        }
+        self.equality()
+    }
+
+    fn is_word_not(&self) -> bool {
+        self.peek().kind == TokenType::BANG && self.peek().lexeme != "!"
+    }
 
     fn equality(&mut self) -> Result<Expr, VlError> {
         let mut expr = self.comparison()?;
-        // Nuốt sạch đống dấu = (BANG) nếu chúng đứng sát nhau (như ==)
-        while matches!(self.peek().kind, TokenType::BANG) {
-            self.advance(); // Ăn dấu = đầu tiên
-            if matches!(self.peek().kind, TokenType::BANG) { self.advance(); } // Ăn dấu = thứ hai nếu có
+        while matches!(self.peek().kind, TokenType::EQ_EQ | TokenType::BANG_EQ) {
+            let op = self.advance().kind;
             let right = self.comparison()?;
-            expr = Expr::Binary { left: Box::new(expr), op: TokenType::BANG, right: Box::new(right) };
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
         }
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, VlError> {
         let mut expr = self.term()?;
-        while matches!(self.peek().kind, TokenType::GT | TokenType::LT) {
-            let op = self.advance();
+        while matches!(self.peek().kind, TokenType::GT | TokenType::GE | TokenType::LT | TokenType::LE) {
+            let op = self.advance().kind;
             let right = self.term()?;
-            expr = Expr::Binary { left: Box::new(expr), op: op.kind, right: Box::new(right) };
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
         }
         Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, VlError> {
-        let mut expr = self.call()?;
+        let mut expr = self.factor()?;
         while matches!(self.peek().kind, TokenType::PLUS | TokenType::MINUS) {
-            let op = self.advance();
-            let right = self.call()?;
-            expr = Expr::Binary { left: Box::new(expr), op: op.kind, right: Box::new(right) };
+            let op = self.advance().kind;
+            let right = self.factor()?;
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, VlError> {
+        let mut expr = self.unary()?;
+        while matches!(self.peek().kind, TokenType::STAR | TokenType::SLASH | TokenType::BACKSLASH | TokenType::PERCENT) {
+            let op = self.advance().kind;
+            let right = self.unary()?;
+            expr = Expr::Binary { left: Box::new(expr), op, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    // `-2 ** 2` là -(2 ** 2), giống toán học
+    fn unary(&mut self) -> Result<Expr, VlError> {
+        if self.is_word_not() {
+            return Err(self.error("'khong' lỏng hơn phép so sánh nên phải đứng đầu điều kiện, viết (khong ...) nếu nằm giữa biểu thức"));
+        }
+        if matches!(self.peek().kind, TokenType::MINUS | TokenType::BANG) {
+            let op = self.advance().kind;
+            let right = self.unary()?;
+            return Ok(Expr::Unary { op, right: Box::new(right) });
+        }
+        self.power()
+    }
+
+    // `**` kết hợp phải: 2 ** 3 ** 2 = 2 ** 9
+    fn power(&mut self) -> Result<Expr, VlError> {
+        let expr = self.call()?;
+        if let TokenType::STAR_STAR = self.peek().kind {
+            let op = self.advance().kind;
+            let right = self.unary()?;
+            return Ok(Expr::Binary { left: Box::new(expr), op, right: Box::new(right) });
         }
         Ok(expr)
     }
 
+    // Sau một biểu thức cơ bản có thể là lời gọi `(...)`, chỉ số `[...]` hoặc `.ten`, lặp lại tuỳ ý
     fn call(&mut self) -> Result<Expr, VlError> {
         let mut expr = self.primary()?;
         loop {
-            if let TokenType::LPAREN = self.peek().kind {
-                self.advance();
-                let mut args = vec![];
-                if !matches!(self.peek().kind, TokenType::RPAREN) {
-                    loop {
-                        args.push(self.parse_expr()?);
-                        if let TokenType::COMMA = self.peek().kind { self.advance(); } else { break; }
-                    }
+            match self.peek().kind {
+                TokenType::LBRACKET => { self.advance(); expr = self.index(expr)?; }
+                TokenType::DOT => {
+                    self.advance();
+                    let name = self.identifier("tên khoá")?;
+                    expr = Expr::Index { object: Box::new(expr), index: Box::new(Expr::Literal(TokenType::CHUOI(name))) };
                 }
-                self.consume(TokenType::RPAREN, "Thieu )")?;
-                if let Expr::Variable(name) = expr {
-                    expr = Expr::Call { callee: name, args };
+                // `in(a) (b)`: lời gọi đã xong mà còn `(` thì là hai câu lệnh viết liền nhau
+                TokenType::LPAREN if matches!(expr, Expr::Call { .. }) => return Err(self.expect(&["xuống dòng", "';'"])),
+                TokenType::LPAREN if !matches!(expr, Expr::Variable(_)) => return Err(self.error("chỉ gọi được hàm bằng tên")),
+                TokenType::LPAREN => {
+                    self.advance();
+                    let mut args = vec![];
+                    if !matches!(self.peek().kind, TokenType::RPAREN) {
+                        loop {
+                            args.push(self.parse_expr()?);
+                            if !self.list_separator()? { break; }
+                        }
+                    }
+                    self.consume(TokenType::RPAREN, "')'")?;
+                    if let Expr::Variable(name) = expr { expr = Expr::Call { callee: name, args }; }
                 }
-            } else { break; }
+                _ => return Ok(expr),
+            }
         }
-        Ok(expr)
+    }
+
+    // Phần sau `[`: `i]` hoặc `a:b]`
+    fn index(&mut self, object: Expr) -> Result<Expr, VlError> {
+        let object = Box::new(object);
+        let start = if let TokenType::COLON = self.peek().kind { None } else { Some(Box::new(self.parse_expr()?)) };
+        match (self.peek().kind.clone(), start) {
+            (TokenType::COLON, start) => {
+                self.advance();
+                let end = if let TokenType::RBRACKET = self.peek().kind { None } else { Some(Box::new(self.parse_expr()?)) };
+                self.consume(TokenType::RBRACKET, "']'")?;
+                Ok(Expr::Slice { object, start, end })
+            }
+            (TokenType::RBRACKET, Some(index)) => { self.advance(); Ok(Expr::Index { object, index }) }
+            _ => Err(self.expect(&["']'", "':'"])),
+        }
+    }
+
+    // `[a, b, c]`, được phép có dấu phẩy sau phần tử cuối
+    fn list_literal(&mut self) -> Result<Expr, VlError> {
+        let mut items = vec![];
+        while !matches!(self.peek().kind, TokenType::RBRACKET) {
+            items.push(self.parse_expr()?);
+            match self.peek().kind {
+                TokenType::COMMA => { self.advance(); }
+                TokenType::RBRACKET => break,
+                _ => return Err(self.expect(&["']'", "','"])),
+            }
+        }
+        self.advance();
+        Ok(Expr::List(items))
+    }
+
+    // `{ "ten": "Rong", "mau": 100 }`; được xuống dòng giữa các cặp và có dấu phẩy cuối
+    fn map_literal(&mut self) -> Result<Expr, VlError> {
+        let mut pairs = vec![];
+        loop {
+            self.skip_newlines();
+            if let TokenType::RBRACE = self.peek().kind { break; }
+            let key = self.parse_expr()?;
+            self.consume(TokenType::COLON, "':'")?;
+            self.skip_newlines();
+            pairs.push((key, self.parse_expr()?));
+            self.skip_newlines();
+            match self.peek().kind {
+                TokenType::COMMA => { self.advance(); }
+                TokenType::RBRACE => break,
+                _ => return Err(self.expect(&["'}'", "','"])),
+            }
+        }
+        self.advance();
+        Ok(Expr::Map(pairs))
     }
 
     fn primary(&mut self) -> Result<Expr, VlError> {
+        // Token không mở đầu được biểu thức thì để nguyên cho bước đồng bộ sau lỗi
+        if !matches!(self.peek().kind, TokenType::Int(_) | TokenType::Float(_) | TokenType::CHUOI(_) | TokenType::CHUOI_MAU(_)
+            | TokenType::TEN(_) | TokenType::IN | TokenType::TH | TokenType::DOC_FILE | TokenType::NGAUNHIEN | TokenType::LPAREN
+            | TokenType::LBRACKET | TokenType::LBRACE) {
+            return Err(self.expect(&["biểu thức"]));
+        }
         let token = self.advance();
         match token.kind {
             TokenType::Int(n) => Ok(Expr::Literal(TokenType::Int(n))),
+            TokenType::Float(n) => Ok(Expr::Literal(TokenType::Float(n))),
             TokenType::CHUOI(s) => Ok(Expr::Literal(TokenType::CHUOI(s))),
+            TokenType::CHUOI_MAU(pieces) => self.interpolation(pieces),
             TokenType::TEN(n) => Ok(Expr::Variable(n)),
             TokenType::IN => Ok(Expr::Variable("in".into())),
             TokenType::TH => Ok(Expr::Variable("ngu".into())),
             TokenType::DOC_FILE => Ok(Expr::Variable("nhap".into())),
             TokenType::NGAUNHIEN => Ok(Expr::Variable("ngaunhien".into())),
+            TokenType::LBRACKET => self.list_literal(),
+            TokenType::LBRACE => self.map_literal(),
             TokenType::LPAREN => {
                 let expr = self.parse_expr()?;
-                self.consume(TokenType::RPAREN, "Thieu )")?;
+                self.consume(TokenType::RPAREN, "')'")?;
                 Ok(expr)
             }
-            _ => Err(VlError { msg_vi: format!("Loi tai: {:?}", token.lexeme), msg_en: "".into(), line: 0, col: 0 }),
+            _ => Err(VlError::at(token.span, "mong đợi biểu thức")),
+        }
+    }
+
+    // Mỗi mảnh `{...}` được quét và phân tích lại như một biểu thức độc lập
+    fn interpolation(&mut self, pieces: Vec<StrPiece>) -> Result<Expr, VlError> {
+        let mut parts = vec![];
+        for piece in pieces {
+            match piece {
+                StrPiece::Lit(s) => parts.push(InterpPart::Lit(s)),
+                StrPiece::Code { src, spec, span } => {
+                    let mut sub = Parser::new(Lexer::new(&src).starting_at(span).with_dialect(self.lexer.dialect()));
+                    if sub.is_at_end() { return Err(VlError::at(span, "thiếu biểu thức trong {}")); }
+                    let expr = sub.parse_expr();
+                    if let Some(e) = sub.lexer.take_errors().into_iter().next() { return Err(e); }
+                    let expr = expr?;
+                    if !sub.is_at_end() { return Err(sub.error("biểu thức trong {} chưa kết thúc")); }
+                    if let Some(spec) = &spec {
+                        if vl_vm::FormatSpec::parse(spec).is_none() {
+                            return Err(VlError::at(span, &format!("định dạng không hợp lệ '{}'", spec)));
+                        }
+                    }
+                    parts.push(InterpPart::Expr(expr, spec));
+                }
+            }
         }
+        Ok(Expr::Interpolated(parts))
     }
 
     fn if_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
-        self.advance(); // skip 'neu'
+        let span = self.advance().span; // skip 'neu'
         let condition = self.parse_expr()?;
-        self.consume(TokenType::LBRACE, "Thieu {")?;
-        let mut then_branch = vec![];
-        while !matches!(self.peek().kind, TokenType::RBRACE) && !self.is_at_end() {
-            if let Some(s) = self.parse_stmt()? { then_branch.push(s); }
+        let then_branch = self.block()?;
+        // `nguoc_lai` được phép nằm cùng dòng với `}` hoặc ở dòng kế tiếp
+        if let TokenType::NEWLINE = self.peek().kind {
+            self.advance();
+            if !matches!(self.peek().kind, TokenType::NGUOC_LAI) {
+                self.terminated = true;
+                return Ok(Some(Stmt::If { condition, then_branch, else_branch: None, span }));
+            }
         }
-        self.consume(TokenType::RBRACE, "Thieu }")?;
-        Ok(Some(Stmt::If { condition, then_branch, else_branch: None }))
+        let else_branch = match self.peek().kind {
+            TokenType::NGUOC_LAI => {
+                self.advance();
+                if let TokenType::NEU = self.peek().kind { self.if_stmt()?.map(|s| vec![s]) } else { Some(self.block()?) }
+            }
+            _ => None,
+        };
+        Ok(Some(Stmt::If { condition, then_branch, else_branch, span }))
     }
 
     fn while_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
-        self.advance(); // skip 'lap'
+        let span = self.advance().span; // skip 'lap'
         let condition = self.parse_expr()?;
-        self.consume(TokenType::LBRACE, "Thieu {")?;
+        let body = self.block()?;
+        Ok(Some(Stmt::While { label: None, condition, body, span }))
+    }
+
+    // `'ten: lap ...`, `'ten: cho ...` hoặc `'ten: moi ...`
+    fn labeled_loop(&mut self) -> Result<Option<Stmt>, VlError> {
+        let TokenType::LABEL(name) = self.advance().kind else { unreachable!() };
+        self.consume(TokenType::COLON, "':'")?;
+        let mut stmt = match self.peek().kind {
+            TokenType::LAP => self.while_stmt()?,
+            TokenType::CHO => self.for_stmt()?,
+            TokenType::MOI => self.for_each_stmt()?,
+            _ => return Err(self.expect(&["'lap'", "'cho'", "'moi'"])),
+        };
+        if let Some(Stmt::While { label, .. } | Stmt::For { label, .. } | Stmt::ForEach { label, .. }) = &mut stmt {
+            *label = Some(name);
+        }
+        Ok(stmt)
+    }
+
+    // `dung` / `tiep`, có thể kèm nhãn của vòng lặp bên ngoài
+    fn jump_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let token = self.advance();
+        let label = if let TokenType::LABEL(name) = &self.peek().kind { let name = name.clone(); self.advance(); Some(name) } else { None };
+        let span = token.span;
+        Ok(Some(if token.kind == TokenType::DUNG { Stmt::Break { label, span } } else { Stmt::Continue { label, span } }))
+    }
+
+    fn for_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'cho'
+        let var = self.identifier("tên biến đếm")?;
+        self.consume(TokenType::TU, "'tu'")?;
+        let start = self.parse_expr()?;
+        let inclusive = match self.peek().kind {
+            TokenType::DEN => true,
+            TokenType::TRUOC => false,
+            _ => return Err(self.expect(&["'den'", "'truoc'"])),
+        };
+        self.advance();
+        let end = self.parse_expr()?;
+        let step = if let TokenType::BUOC = self.peek().kind { self.advance(); Some(self.parse_expr()?) } else { None };
+        let body = self.block()?;
+        Ok(Some(Stmt::For { label: None, var, start, end, inclusive, step, body, span }))
+    }
+
+    fn for_each_stmt(&mut self) -> Result<Option<Stmt>, VlError> {
+        let span = self.advance().span; // skip 'moi'
+        let var = self.identifier("tên biến")?;
+        self.consume(TokenType::TRONG, "'trong'")?;
+        let iterable = self.parse_expr()?;
+        let body = self.block()?;
+        Ok(Some(Stmt::ForEach { label: None, var, iterable, body, span }))
+    }
+
+    // `{` câu lệnh, mỗi câu kết thúc bằng xuống dòng hoặc `;` `}`
+    fn block(&mut self) -> Result<Vec<Stmt>, VlError> {
+        self.consume(TokenType::LBRACE, "'{'")?;
+        self.block_body()
+    }
+
+    // Phần sau `{` của khối, tới hết `}`
+    fn block_body(&mut self) -> Result<Vec<Stmt>, VlError> {
         let mut body = vec![];
-        while !matches!(self.peek().kind, TokenType::RBRACE) && !self.is_at_end() {
-            if let Some(s) = self.parse_stmt()? { body.push(s); }
+        loop {
+            self.skip_terminators();
+            if matches!(self.peek().kind, TokenType::RBRACE) || self.is_at_end() { break; }
+            body.extend(self.statement());
+        }
+        self.consume(TokenType::RBRACE, "'}'")?;
+        Ok(body)
+    }
+
+    fn end_of_stmt(&mut self) -> Result<(), VlError> {
+        if std::mem::take(&mut self.terminated) { return Ok(()); }
+        match self.peek().kind {
+            TokenType::NEWLINE | TokenType::SEMICOLON => { self.advance(); Ok(()) }
+            TokenType::EOF | TokenType::RBRACE => Ok(()),
+            _ => Err(self.expect(&["xuống dòng", "';'"])),
+        }
+    }
+
+    // Sau một phần tử trong `( )`: `,` thì còn phần tử tiếp, `)` thì hết, còn lại là lỗi
+    fn list_separator(&mut self) -> Result<bool, VlError> {
+        match self.peek().kind {
+            TokenType::COMMA => { self.advance(); Ok(true) }
+            TokenType::RPAREN => Ok(false),
+            _ => Err(self.expect(&["')'", "','"])),
+        }
+    }
+    fn skip_terminators(&mut self) {
+        while matches!(self.peek().kind, TokenType::NEWLINE | TokenType::SEMICOLON) { self.advance(); }
+    }
+    fn skip_newlines(&mut self) {
+        while matches!(self.peek().kind, TokenType::NEWLINE) { self.advance(); }
+    }
+
+    fn advance(&mut self) -> Token<'a> {
+        if self.is_at_end() { return self.current.clone(); }
+        let next = self.lexer.next().unwrap_or(Token { kind: TokenType::EOF, lexeme: "", span: self.current.span });
+        match self.current.kind {
+            TokenType::LBRACE => self.braces += 1,
+            TokenType::RBRACE => self.braces = self.braces.saturating_sub(1),
+            _ => {}
         }
-        self.consume(TokenType::RBRACE, "Thieu }")?;
-        Ok(Some(Stmt::While { condition, body }))
+        std::mem::replace(&mut self.current, next)
+    }
+    fn peek(&self) -> &Token<'a> { &self.current }
+    fn is_at_end(&self) -> bool { self.current.kind == TokenType::EOF }
+    fn error(&self, msg: &str) -> VlError { VlError::at(self.peek().span, msg) }
+    fn expect(&self, expected: &[&str]) -> VlError { VlError::expected(self.peek().span, expected, &describe(self.peek())) }
+    fn consume(&mut self, kind: TokenType, expected: &str) -> Result<Token<'a>, VlError> {
+        if self.peek().kind == kind { Ok(self.advance()) } else { Err(self.expect(&[expected])) }
     }
+    fn identifier(&mut self, expected: &str) -> Result<String, VlError> {
+        if matches!(self.peek().kind, TokenType::TEN(_)) {
+            if let TokenType::TEN(name) = self.advance().kind { return Ok(name); }
+        }
+        Err(self.expect(&[expected]))
+    }
+}
 
-    fn advance(&mut self) -> Token { if !self.is_at_end() { self.current += 1; } self.tokens[self.current - 1].clone() }
-    fn peek(&self) -> Token { self.tokens[self.current].clone() }
-    fn is_at_end(&self) -> bool { self.tokens[self.current].kind == TokenType::EOF }
-    fn error(&self, msg: &str) -> VlError { VlError { msg_vi: msg.into(), msg_en: "".into(), line: 0, col: 0 } }
-    fn consume(&mut self, kind: TokenType, msg: &str) -> Result<Token, VlError> {
-        if self.peek().kind == kind { Ok(self.advance()) } else { Err(self.error(msg)) }
+// Tên của token trong thông báo lỗi: chính lexeme, trừ xuống dòng và cuối file
+fn describe(token: &Token) -> String {
+    match token.kind {
+        TokenType::NEWLINE => "xuống dòng".into(),
+        TokenType::EOF => "cuối file".into(),
+        _ => format!("'{}'", token.lexeme),
     }
 }
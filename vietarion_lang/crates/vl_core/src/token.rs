@@ -1,14 +1,122 @@
 #[derive(Debug, Clone, PartialEq)]
+#[allow(non_camel_case_types)]
 pub enum TokenType {
-    TB, LAP, IN, NGAUNHIEN, NEU, NGUOC_LAI, TH, DOC_FILE,
-    PLUS, MINUS, GT, LT, BANG,
-    LPAREN, RPAREN, LBRACE, RBRACE, COMMA,
-    TEN(String), CHUOI(String), Int(f64),
+    TB, LAP, IN, NGAUNHIEN, NEU, NGUOC_LAI, TH, DOC_FILE, HAM, TRA_VE,
+    CHO, TU, DEN, TRUOC, BUOC, MOI, TRONG, DUNG, TIEP, KIEU, LOAI,
+    PLUS, MINUS, STAR, STAR_STAR, SLASH, BACKSLASH, PERCENT,
+    ASSIGN, EQ_EQ, BANG_EQ, GT, GE, LT, LE,
+    BANG, AND_AND, OR_OR,
+    LPAREN, RPAREN, LBRACE, RBRACE, LBRACKET, RBRACKET, COMMA, COLON, DOT, SHARP,
+    ARROW, SEMICOLON, NEWLINE,
+    TEN(String), LABEL(String), CHUOI(String), CHUOI_MAU(Vec<StrPiece>), Int(i64), Float(f64),
+    DOC(String),
     EOF
 }
 
+/// Bộ từ khoá dùng khi quét: tiếng Việt (mặc định) hoặc tiếng Anh.
+/// Chọn qua `Lexer::with_dialect` hoặc dòng đầu file `#[ngon_ngu(en)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dialect { #[default] Vi, En }
+
+// Cách viết đầu tiên của mỗi từ khoá là cách viết chuẩn, dùng khi dịch file sang phương ngữ khác
+// `in_dong` và `doc_file` là tên cũ của `in` và `nhap`, giữ lại để script cũ chạy như trước
+const VI_KEYWORDS: &[(&str, TokenType)] = &[
+    ("tb", TokenType::TB),
+    ("lap", TokenType::LAP), ("lặp", TokenType::LAP),
+    ("in", TokenType::IN), ("in_dong", TokenType::IN),
+    ("neu", TokenType::NEU), ("nếu", TokenType::NEU),
+    ("nguoc_lai", TokenType::NGUOC_LAI), ("ngược_lại", TokenType::NGUOC_LAI),
+    ("ngu", TokenType::TH), ("ngủ", TokenType::TH),
+    ("ngaunhien", TokenType::NGAUNHIEN), ("ngẫunhiên", TokenType::NGAUNHIEN), ("ngẫu_nhiên", TokenType::NGAUNHIEN),
+    ("nhap", TokenType::DOC_FILE), ("nhập", TokenType::DOC_FILE), ("doc_file", TokenType::DOC_FILE),
+    ("ham", TokenType::HAM), ("hàm", TokenType::HAM),
+    ("tra_ve", TokenType::TRA_VE), ("trả_về", TokenType::TRA_VE),
+    ("va", TokenType::AND_AND), ("và", TokenType::AND_AND),
+    ("hoac", TokenType::OR_OR), ("hoặc", TokenType::OR_OR),
+    ("khong", TokenType::BANG), ("không", TokenType::BANG),
+    ("cho", TokenType::CHO),
+    ("tu", TokenType::TU), ("từ", TokenType::TU),
+    ("den", TokenType::DEN), ("đến", TokenType::DEN),
+    ("truoc", TokenType::TRUOC), ("trước", TokenType::TRUOC),
+    ("buoc", TokenType::BUOC), ("bước", TokenType::BUOC),
+    ("moi", TokenType::MOI), ("mỗi", TokenType::MOI),
+    ("trong", TokenType::TRONG),
+    ("dung", TokenType::DUNG), ("dừng", TokenType::DUNG),
+    ("tiep", TokenType::TIEP), ("tiếp", TokenType::TIEP),
+    ("kieu", TokenType::KIEU), ("kiểu", TokenType::KIEU),
+    ("loai", TokenType::LOAI), ("loại", TokenType::LOAI),
+];
+
+const EN_KEYWORDS: &[(&str, TokenType)] = &[
+    ("let", TokenType::TB),
+    ("while", TokenType::LAP),
+    ("print", TokenType::IN),
+    ("if", TokenType::NEU),
+    ("else", TokenType::NGUOC_LAI),
+    ("sleep", TokenType::TH),
+    ("random", TokenType::NGAUNHIEN),
+    ("input", TokenType::DOC_FILE),
+    ("fn", TokenType::HAM),
+    ("return", TokenType::TRA_VE),
+    ("for", TokenType::CHO),
+    ("from", TokenType::TU),
+    ("to", TokenType::DEN),
+    ("until", TokenType::TRUOC),
+    ("step", TokenType::BUOC),
+    ("each", TokenType::MOI),
+    ("in", TokenType::TRONG),
+    ("break", TokenType::DUNG),
+    ("continue", TokenType::TIEP),
+    ("struct", TokenType::KIEU),
+    ("enum", TokenType::LOAI),
+    // `not` lỏng như `khong`; `&&`/`||` chỉ để lệnh dịch biết cách viết `va`/`hoac`
+    ("not", TokenType::BANG),
+    ("&&", TokenType::AND_AND),
+    ("||", TokenType::OR_OR),
+];
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name { "vi" => Some(Dialect::Vi), "en" => Some(Dialect::En), _ => None }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self { Dialect::Vi => "vi", Dialect::En => "en" }
+    }
+
+    fn keywords(self) -> &'static [(&'static str, TokenType)] {
+        match self { Dialect::Vi => VI_KEYWORDS, Dialect::En => EN_KEYWORDS }
+    }
+
+    /// Từ khoá ứng với `text` (đã chuẩn hoá NFC) trong phương ngữ này.
+    pub fn keyword(self, text: &str) -> Option<TokenType> {
+        self.keywords().iter().find(|(k, _)| *k == text).map(|(_, t)| t.clone())
+    }
+
+    /// Cách viết chuẩn của từ khoá `kind` trong phương ngữ này.
+    pub fn spelling(self, kind: &TokenType) -> Option<&'static str> {
+        self.keywords().iter().find(|(_, t)| t == kind).map(|(k, _)| *k)
+    }
+}
+
+/// Một mảnh của chuỗi nội suy `"Máu: {mau:>5}"`: chữ thường hoặc mã nguồn nằm trong `{}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrPiece {
+    Lit(String),
+    Code { src: String, spec: Option<String>, span: Span },
+}
+
+/// Vị trí trong file nguồn: dòng và cột tính từ 1, offset tính theo byte.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub offset: usize,
+}
+
 #[derive(Debug, Clone)]
-pub struct Token {
+pub struct Token<'a> {
     pub kind: TokenType,
-    pub lexeme: String,
+    pub lexeme: &'a str,
+    pub span: Span,
 }
@@ -0,0 +1,61 @@
+use crate::lexer::{header_dialect, Lexer};
+use crate::token::{Dialect, StrPiece, TokenType};
+use crate::VlError;
+
+/// Đổi từ khoá của `source` sang phương ngữ `to`. Chỉ thay đúng các từ khoá theo vị trí byte,
+/// nên chú thích, khoảng trắng và cách xuống dòng được giữ nguyên.
+/// `from` là phương ngữ của file khi nó không có dòng `#[ngon_ngu(..)]`.
+pub fn translate(source: &str, from: Dialect, to: Dialect) -> Result<String, Vec<VlError>> {
+    let from = header_dialect(source).unwrap_or(from);
+    let mut edits = vec![];
+    let mut errors = vec![];
+    collect_edits(Lexer::new(source).with_dialect(from), from, to, &mut edits, &mut errors);
+    if !errors.is_empty() { return Err(errors); }
+
+    edits.sort_by_key(|(offset, _, _)| *offset);
+    let mut out = String::with_capacity(source.len());
+    // File gốc tiếng Việt không có dòng khai báo thì phải thêm vào, không thì lexer sẽ đọc nhầm
+    if header_dialect(source).is_none() && to != Dialect::default() {
+        out.push_str(&format!("#[ngon_ngu({})]\n", to.name()));
+    }
+    let mut last = 0;
+    for (offset, len, text) in edits {
+        out.push_str(&source[last..offset]);
+        out.push_str(text);
+        last = offset + len;
+    }
+    out.push_str(&source[last..]);
+    Ok(out)
+}
+
+fn collect_edits(mut lexer: Lexer, from: Dialect, to: Dialect, edits: &mut Vec<(usize, usize, &'static str)>, errors: &mut Vec<VlError>) {
+    let mut in_header = false;
+    for token in lexer.by_ref() {
+        match &token.kind {
+            TokenType::TEN(name) if name == "ngon_ngu" => in_header = true,
+            TokenType::TEN(_) if in_header => {
+                edits.push((token.span.offset, token.lexeme.len(), to.name()));
+                in_header = false;
+            }
+            TokenType::TEN(name) if from != to && to.keyword(name).is_some() => {
+                errors.push(VlError::at(token.span, &format!("tên '{}' trùng với từ khoá khi dịch sang '{}', hãy đổi tên trước", name, to.name())));
+            }
+            // Mã trong `{}` của chuỗi nội suy cũng có từ khoá; chuỗi """ đã bỏ thụt lề nên không còn đúng vị trí
+            TokenType::CHUOI_MAU(pieces) if !token.lexeme.starts_with("\"\"\"") => {
+                for piece in pieces {
+                    if let StrPiece::Code { src, span, .. } = piece {
+                        collect_edits(Lexer::new(src).starting_at(*span).with_dialect(from), from, to, edits, errors);
+                    }
+                }
+            }
+            // Ký hiệu như `&&`, `!` dùng được ở mọi phương ngữ nên giữ nguyên, chỉ dịch từ khoá viết bằng chữ
+            kind if token.lexeme.starts_with(char::is_alphabetic) => {
+                if let Some(word) = to.spelling(kind) {
+                    edits.push((token.span.offset, token.lexeme.len(), word));
+                }
+            }
+            _ => {}
+        }
+    }
+    errors.extend(lexer.take_errors());
+}
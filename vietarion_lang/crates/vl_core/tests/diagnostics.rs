@@ -0,0 +1,34 @@
+use std::fs;
+use vl_core::compiler::Compiler;
+use vl_core::lexer::Lexer;
+use vl_core::parser::Parser;
+
+// Mỗi tệp trong tests/loi ghi các cảnh báo rồi các lỗi mong đợi, theo thứ tự, bằng dòng `// canh_bao: ...` và `// loi: ...`
+fn diagnostics(source: &str) -> Vec<String> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let mut warnings = vec![];
+    let errors = match parser.parse() {
+        Ok(stmts) => {
+            let mut compiler = Compiler::new();
+            let errors = compiler.compile(stmts).err().unwrap_or_default();
+            warnings = compiler.warnings;
+            errors
+        }
+        Err(errors) => errors,
+    };
+    let warnings = parser.warnings.iter().chain(&warnings).map(|w| format!("canh_bao: {}", w));
+    warnings.chain(errors.iter().map(|e| format!("loi: {}", e))).collect()
+}
+
+#[test]
+fn reported_diagnostics() {
+    let mut paths: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/loi")).unwrap().map(|e| e.unwrap().path()).collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let expected: Vec<_> = source.lines().filter_map(|l| l.strip_prefix("// "))
+            .filter(|l| l.starts_with("canh_bao: ") || l.starts_with("loi: ")).map(str::to_string).collect();
+        assert_eq!(diagnostics(&source), expected, "{}", path.display());
+    }
+}
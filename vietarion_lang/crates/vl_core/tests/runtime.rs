@@ -0,0 +1,46 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+use vl_core::compiler::Compiler;
+use vl_core::lexer::Lexer;
+use vl_core::parser::Parser;
+use vl_vm::VM;
+
+// Bộ đệm dùng chung để đọc lại những gì chương trình đã in
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().extend_from_slice(buf); Ok(buf.len()) }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+// Cảnh báo biên dịch, các dòng in ra rồi lỗi khi chạy (nếu có), theo đúng cách viết trong tệp kiểm thử
+fn run(source: &str) -> Vec<String> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let stmts = parser.parse().unwrap_or_else(|e| panic!("lỗi cú pháp: {}", e[0]));
+    let mut compiler = Compiler::new();
+    let chunk = compiler.compile(stmts);
+    let mut lines: Vec<String> = parser.warnings.iter().chain(&compiler.warnings).map(|w| format!("canh_bao: {}", w)).collect();
+    let chunk = chunk.unwrap_or_else(|e| panic!("lỗi biên dịch: {}", e[0]));
+    let out = Output::default();
+    let result = VM::new().with_output(out.clone()).run(chunk);
+    lines.extend(String::from_utf8(out.0.take()).unwrap().lines().map(|l| format!("ra: {}", l)));
+    if let Err(e) = result { lines.push(format!("loi: {}", e)); }
+    lines.iter().map(|l| l.trim_end().to_string()).collect()
+}
+
+// Mỗi tệp trong tests/chay ghi kết quả mong đợi bằng các dòng `// canh_bao: ...`, `// ra: ...` và `// loi: ...`
+#[test]
+fn program_output() {
+    let mut paths: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/chay")).unwrap().map(|e| e.unwrap().path()).collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap();
+        let expected: Vec<_> = source.lines().filter_map(|l| l.strip_prefix("// "))
+            .filter(|l| ["canh_bao:", "ra:", "loi:"].iter().any(|p| l.starts_with(p))).map(|l| l.trim_end().to_string()).collect();
+        assert_eq!(run(&source), expected, "{}", path.display());
+    }
+}
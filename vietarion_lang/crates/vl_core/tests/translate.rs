@@ -0,0 +1,45 @@
+use vl_core::token::Dialect;
+use vl_core::translate::translate;
+
+const VI: &str = r#"#[ngon_ngu(vi)]
+// Chú thích giữ nguyên: tb neu lap
+/// Tính máu còn lại
+ham con_lai(mau, st) {
+    neu mau > st { tra_ve mau - st } nguoc_lai { tra_ve 0 }
+}
+
+tb mau = 100
+lap mau > 0 {
+    mau = con_lai(mau, 30)
+    in("Máu: {con_lai(mau, 0):>4}")   /* khối chú thích */
+}
+cho i tu 1 den 3 { in_lien("{i} ") }
+in(khong mau == 0)
+"#;
+
+#[test]
+fn vi_en_vi_round_trip() {
+    let en = translate(VI, Dialect::Vi, Dialect::En).unwrap();
+    assert!(en.starts_with("#[ngon_ngu(en)]\n// Chú thích giữ nguyên: tb neu lap\n/// Tính máu còn lại\nfn con_lai(mau, st) {"));
+    assert!(en.contains("if mau > st { return mau - st } else { return 0 }"));
+    assert!(en.contains("let mau = 100\nwhile mau > 0 {"));
+    assert!(en.contains(r#"print("Máu: {con_lai(mau, 0):>4}")   /* khối chú thích */"#));
+    assert!(en.contains("for i from 1 to 3 {"));
+    assert!(en.contains("print(not mau == 0)"));
+    assert_eq!(translate(&en, Dialect::Vi, Dialect::En).unwrap(), en);
+    assert_eq!(translate(&en, Dialect::En, Dialect::Vi).unwrap(), VI);
+}
+
+#[test]
+fn headerless_english_uses_from() {
+    let en = "let x = 1\nif x == 1 { print(\"{x}\") }\n";
+    assert_eq!(translate(en, Dialect::En, Dialect::Vi).unwrap(), "tb x = 1\nneu x == 1 { in(\"{x}\") }\n");
+    // Không có `--from` thì file được coi là tiếng Việt, `let` chỉ là một tên
+    assert_eq!(translate(en, Dialect::Vi, Dialect::Vi).unwrap(), en);
+}
+
+#[test]
+fn name_clashing_with_target_keyword_is_an_error() {
+    let errors = translate("tb print = 1\n", Dialect::Vi, Dialect::En).unwrap_err();
+    assert!(errors[0].msg_vi.contains("'print'"));
+}
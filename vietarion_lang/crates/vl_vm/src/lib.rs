@@ -1,65 +1,564 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::io::{self, Write};
 use std::fs;
+use std::rc::Rc;
+use std::cell::RefCell;
+use unicode_segmentation::UnicodeSegmentation;
 
+// Danh sách, bảng và bản ghi dùng chung theo tham chiếu: gán `b = a` rồi sửa `b[0]` thì `a` cũng đổi.
+// Bảng là các cặp (khoá, giá trị) theo thứ tự thêm vào để in và duyệt luôn ra cùng một thứ tự.
+// Bản ghi giữ giá trị các trường theo thứ tự khai báo trong `Record`.
+// Biến thể của loại gồm loại, chỉ số biến thể và các giá trị nó mang; không sửa được sau khi tạo.
 #[derive(Debug, Clone)]
-pub enum Value { Number(f64), Str(String), Nil }
+pub enum Value {
+    Int(i64), Number(f64), Str(String), Bool(bool), Function(Rc<Function>),
+    List(Rc<RefCell<Vec<Value>>>), Map(Rc<RefCell<Vec<(Value, Value)>>>), Record(Rc<Record>, Rc<RefCell<Vec<Value>>>),
+    Variant(Rc<Enum>, usize, Rc<Vec<Value>>), Nil,
+}
+
+/// Kiểu ghi sau dấu `:` như `tb x: nguyen = 10`, được kiểm tra lúc chạy mỗi khi gán.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type { Nguyen, Thuc, Logic, Chuoi, List(Box<Type>), Function { params: Vec<Type>, ret: Option<Box<Type>> } }
+
+impl Type {
+    /// Tên kiểu cơ bản, tiếng Việt hoặc tiếng Anh.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nguyen" | "nguyên" | "int" => Some(Type::Nguyen),
+            "thuc" | "thực" | "float" => Some(Type::Thuc),
+            "logic" | "bool" => Some(Type::Logic),
+            "chuoi" | "chuỗi" | "str" => Some(Type::Chuoi),
+            _ => None,
+        }
+    }
+
+    // Số nguyên được nhận vào chỗ cần số thực (lệnh 37 đổi luôn sang số thực)
+    pub fn accepts(&self, val: &Value) -> bool {
+        match (self, val) {
+            (Type::Nguyen, Value::Int(_)) | (Type::Thuc, Value::Int(_) | Value::Number(_))
+            | (Type::Logic, Value::Bool(_)) | (Type::Chuoi, Value::Str(_)) => true,
+            (Type::Function { params, .. }, Value::Function(f)) => params.len() == f.arity,
+            (Type::List(t), Value::List(items)) => items.borrow().iter().all(|item| t.accepts(item)),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Nguyen => write!(f, "nguyen"), Type::Thuc => write!(f, "thuc"),
+            Type::Logic => write!(f, "logic"), Type::Chuoi => write!(f, "chuoi"),
+            Type::List(t) => write!(f, "[{}]", t),
+            Type::Function { params, ret } => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "ham({})", params.join(", "))?;
+                if let Some(ret) = ret { write!(f, " -> {}", ret)?; }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Hàm người dùng: thân hàm bắt đầu tại `start` trong `Chunk::code`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function { pub name: String, pub arity: usize, pub start: usize }
+
+/// Kiểu bản ghi `kieu NguoiChoi { ten, mau, giap }`: tên các trường theo thứ tự khai báo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record { pub name: String, pub fields: Vec<String> }
+
+/// Loại `loai TrangThai { Song, BiThuong(mau) }`: mỗi biến thể là tên kèm tên các giá trị nó mang.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum { pub name: String, pub variants: Vec<Record> }
+
+impl Record {
+    fn field(&self, key: &Value) -> Result<usize, String> {
+        let found = if let Value::Str(s) = key { self.fields.iter().position(|f| f == s) } else { None };
+        found.ok_or_else(|| format!("kiểu {} không có trường {}", self.name, key.repr()))
+    }
+}
 
 impl Value {
     pub fn as_number(&self) -> f64 { 
-        match self { Value::Number(n) => *n, Value::Str(s) => s.parse().unwrap_or(0.0), _ => 0.0 } 
+        match self { Value::Int(n) => *n as f64, Value::Number(n) => *n, Value::Str(s) => s.parse().unwrap_or(0.0), Value::Bool(b) => *b as i64 as f64, _ => 0.0 } 
     }
     pub fn is_truthy(&self) -> bool { 
-        match self { Value::Number(n) => *n > 0.0, Value::Str(s) => !s.is_empty(), _ => false } 
+        match self { Value::Int(n) => *n > 0, Value::Number(n) => *n > 0.0, Value::Str(s) => !s.is_empty(), Value::Bool(b) => *b, Value::Function(_) => true, Value::List(l) => !l.borrow().is_empty(), Value::Map(m) => !m.borrow().is_empty(), Value::Record(..) | Value::Variant(..) => true, _ => false } 
+    }
+    /// Tên kiểu của giá trị, dùng trong thông báo lỗi.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "nguyen", Value::Number(_) => "thuc", Value::Str(_) => "chuoi",
+            Value::Bool(_) => "logic", Value::Function(_) => "ham", Value::List(_) => "danh_sach", Value::Map(_) => "bang", Value::Record(..) => "ban_ghi", Value::Variant(..) => "loai", Value::Nil => "rỗng",
+        }
+    }
+    // So sánh bằng không ép kiểu: số khác chuỗi luôn là khác nhau
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(_) | Value::Number(_), Value::Int(_) | Value::Number(_)) => self.as_number() == other.as_number(),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y))
+            }
+            // Hai bảng bằng nhau khi có cùng các cặp, không xét thứ tự
+            (Value::Map(a), Value::Map(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().all(|(k, v)| b.iter().any(|(k2, v2)| k.equals(k2) && v.equals(v2)))
+            }
+            (Value::Record(ta, a), Value::Record(tb, b)) => ta == tb && a.borrow().iter().zip(b.borrow().iter()).all(|(x, y)| x.equals(y)),
+            (Value::Variant(ea, va, a), Value::Variant(eb, vb, b)) => ea == eb && va == vb && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y)),
+            (Value::Nil, Value::Nil) => true,
+            _ => false,
+        }
+    }
+    // Cách in một giá trị nằm bên trong danh sách hoặc bảng: chuỗi kèm ngoặc kép để thấy rõ ranh giới phần tử
+    fn repr(&self) -> String {
+        match self { Value::Str(s) => format!("{:?}", s), Value::Nil => "rỗng".into(), _ => self.to_string() }
+    }
+    // Khoá của bảng chỉ có thể là chuỗi, số nguyên hoặc logic
+    fn check_key(&self) -> Result<(), String> {
+        if matches!(self, Value::Str(_) | Value::Int(_) | Value::Bool(_)) { return Ok(()); }
+        Err(format!("khoá của bảng phải là chuỗi, số nguyên hoặc logic, không phải {} '{}'", self.type_name(), self))
+    }
+}
+
+// Hằng số trong chunk dùng kiểu của vl_bytecode, khi nạp lên stack thì đổi sang giá trị lúc chạy
+impl From<vl_bytecode::Value> for Value {
+    fn from(val: vl_bytecode::Value) -> Self {
+        match val {
+            vl_bytecode::Value::Nguyen(n) => Value::Int(n),
+            vl_bytecode::Value::Thuc(n) => Value::Number(n),
+            vl_bytecode::Value::Logic(b) => Value::Bool(b),
+            vl_bytecode::Value::Chuoi(s) => Value::Str(s),
+            vl_bytecode::Value::Null => Value::Nil,
+        }
+    }
+}
+
+// `lines[i]` là (dòng, cột) trong file nguồn của byte `code[i]`
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self { Value::Int(n) => write!(f, "{}", n), Value::Number(n) => write!(f, "{:?}", n), Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", if *b { "đúng" } else { "sai" }), Value::Function(func) => write!(f, "<hàm {}>", func.name), Value::Nil => Ok(()),
+            Value::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(Value::repr).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Map(pairs) => {
+                let pairs: Vec<String> = pairs.borrow().iter().map(|(k, v)| format!("{}: {}", k.repr(), v.repr())).collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Value::Record(record, vals) => {
+                let fields: Vec<String> = record.fields.iter().zip(vals.borrow().iter()).map(|(name, v)| format!("{}: {}", name, v.repr())).collect();
+                write!(f, "{} {{ {} }}", record.name, fields.join(", "))
+            }
+            Value::Variant(en, v, vals) if vals.is_empty() => write!(f, "{}", en.variants[*v].name),
+            Value::Variant(en, v, vals) => {
+                let vals: Vec<String> = vals.iter().map(Value::repr).collect();
+                write!(f, "{}({})", en.variants[*v].name, vals.join(", "))
+            }
+        }
+    }
+}
+
+/// Định dạng trong chuỗi nội suy: `[[lấp]căn][độ rộng][.độ chính xác]`, ví dụ `.2`, `>10`, `*^8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec { pub fill: char, pub align: Option<char>, pub width: usize, pub precision: Option<usize> }
+
+impl FormatSpec {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let chars: Vec<char> = spec.chars().collect();
+        let is_align = |c: &char| matches!(c, '<' | '>' | '^');
+        let (fill, align, mut i) = match chars.as_slice() {
+            [f, a, ..] if is_align(a) => (*f, Some(*a), 2),
+            [a, ..] if is_align(a) => (' ', Some(*a), 1),
+            _ => (' ', None, 0),
+        };
+        let digits = |i: &mut usize| { let s = *i; while *i < chars.len() && chars[*i].is_ascii_digit() { *i += 1; } chars[s..*i].iter().collect::<String>() };
+        let width = digits(&mut i);
+        let mut precision = None;
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            precision = Some(digits(&mut i).parse().ok()?);
+        }
+        if i != chars.len() { return None; }
+        Some(Self { fill, align, width: if width.is_empty() { 0 } else { width.parse().ok()? }, precision })
+    }
+
+    pub fn apply(&self, val: &Value) -> String {
+        let text = match (val, self.precision) {
+            (Value::Int(_) | Value::Number(_), Some(p)) => format!("{:.*}", p, val.as_number()),
+            (Value::Str(s), Some(p)) => s.chars().take(p).collect(),
+            _ => val.to_string(),
+        };
+        let pad = self.width.saturating_sub(text.chars().count());
+        // Số mặc định căn phải, chuỗi mặc định căn trái như Rust
+        let align = self.align.unwrap_or(if matches!(val, Value::Int(_) | Value::Number(_)) { '>' } else { '<' });
+        let (left, right) = match align { '>' => (pad, 0), '^' => (pad / 2, pad - pad / 2), _ => (0, pad) };
+        let fill = |n: usize| std::iter::repeat_n(self.fill, n).collect::<String>();
+        format!("{}{}{}", fill(left), text, fill(right))
+    }
+}
+
+// Hai số nguyên cho ra số nguyên (tràn số thì chuyển sang số thực), có số thực thì tính bằng số thực
+fn arith(a: &Value, b: &Value, int_op: fn(i64, i64) -> Option<i64>, real_op: fn(f64, f64) -> f64) -> Value {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        if let Some(n) = int_op(*x, *y) { return Value::Int(n); }
+    }
+    Value::Number(real_op(a.as_number(), b.as_number()))
+}
+
+// Phép chia cần kiểm tra kiểu trước khi xét số chia bằng 0
+fn numbers(a: &Value, b: &Value, op: &str) -> Result<(), String> {
+    match [a, b].into_iter().find(|v| !matches!(v, Value::Int(_) | Value::Number(_))) {
+        Some(other) => Err(format!("phép '{}' cần số nhưng nhận {} '{}'", op, other.type_name(), other)),
+        None => Ok(()),
+    }
+}
+
+// Chỉ số âm tính từ cuối; trả về lỗi khi nằm ngoài `len` phần tử
+fn resolve_index(index: &Value, len: usize, what: &str) -> Result<usize, String> {
+    let Value::Int(i) = index else { return Err(format!("chỉ số phải là số nguyên nhưng nhận {} '{}'", index.type_name(), index)) };
+    let pos = if *i < 0 { *i + len as i64 } else { *i };
+    if pos < 0 || pos >= len as i64 { return Err(format!("chỉ số {} nằm ngoài {} có {} phần tử", i, what, len)); }
+    Ok(pos as usize)
+}
+
+// Đầu cắt bỏ trống (rỗng) là đầu/cuối; vượt biên thì kẹp lại thay vì báo lỗi
+fn slice_bound(bound: &Value, len: usize, default: usize) -> Result<usize, String> {
+    match bound {
+        Value::Nil => Ok(default),
+        Value::Int(i) => Ok(if *i < 0 { (len as i64 + i).max(0) as usize } else { (*i as usize).min(len) }),
+        other => Err(format!("đầu cắt phải là số nguyên nhưng nhận {} '{}'", other.type_name(), other)),
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct Chunk { pub code: Vec<u8>, pub constants: Vec<Value>, pub names: Vec<String> }
+pub struct Chunk { pub code: Vec<u8>, pub constants: Vec<vl_bytecode::Value>, pub names: Vec<String>, pub lines: Vec<(u32, u32)>, pub functions: Vec<Function>, pub types: Vec<Type>, pub records: Vec<Rc<Record>>, pub enums: Vec<Rc<Enum>> }
+
+/// Lỗi khi chạy, kèm vị trí trong file nguồn của lệnh gây lỗi.
+#[derive(Debug, Clone)]
+pub struct RuntimeError { pub msg: String, pub line: u32, pub col: u32 }
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[dòng {}, cột {}] {}", self.line, self.col, self.msg)
+    }
+}
+
+const STACK_CAPACITY: usize = 1024;
+// Số lời gọi hàm lồng nhau tối đa, để đệ quy không dừng báo lỗi thay vì ăn hết bộ nhớ
+const MAX_FRAMES: usize = 10_000;
 
 pub struct VM {
-    pub stack: [Value; 256],
-    pub stack_top: usize,
+    // Stack tự lớn lên theo nhu cầu; danh sách dài hay gọi hàm sâu không bị giới hạn bởi kích thước cố định
+    pub stack: Vec<Value>,
     pub globals: HashMap<String, Value>,
-    pub call_stack: Vec<usize>,
+    // (địa chỉ quay về, đáy khung gọi của hàm gọi) cho mỗi lời gọi hàm đang chạy
+    pub call_stack: Vec<(usize, usize)>,
+    // Ô stack của biến cục bộ số 0 trong khung gọi hiện tại
+    base: usize,
+    // Nơi `in`/`in_lien`/`xoa` ghi ra, mặc định là stdout
+    out: Box<dyn Write>,
+}
+
+impl Default for VM {
+    fn default() -> Self { Self::new() }
 }
 
 impl VM {
     pub fn new() -> Self {
-        const NIL: Value = Value::Nil;
-        Self { stack: [NIL; 256], stack_top: 0, globals: HashMap::new(), call_stack: vec![] }
+        Self { stack: Vec::with_capacity(STACK_CAPACITY), globals: HashMap::new(), call_stack: vec![], base: 0, out: Box::new(io::stdout()) }
     }
-    fn push(&mut self, val: Value) { if self.stack_top < 256 { self.stack[self.stack_top] = val; self.stack_top += 1; } }
-    fn pop(&mut self) -> Value { if self.stack_top == 0 { return Value::Nil; } self.stack_top -= 1; std::mem::replace(&mut self.stack[self.stack_top], Value::Nil) }
+    /// Ghi kết quả in ra `out` thay vì stdout, ví dụ để kiểm thử bắt lại kết quả.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self { self.out = Box::new(out); self }
+    fn push(&mut self, val: Value) { self.stack.push(val); }
+    fn pop(&mut self) -> Value { self.stack.pop().unwrap_or(Value::Nil) }
+    // Lấy n giá trị trên đỉnh stack theo đúng thứ tự đã đẩy vào
+    fn pop_n(&mut self, n: usize) -> Vec<Value> { self.stack.split_off(self.stack.len() - n) }
     fn read_u16(&self, code: &[u8], ip: &mut usize) -> usize { let res = ((code[*ip] as u16) << 8 | (code[*ip + 1] as u16)) as usize; *ip += 2; res }
 
-    pub fn run(&mut self, chunk: Chunk) {
+    pub fn run(&mut self, chunk: Chunk) -> Result<(), RuntimeError> {
         let mut ip = 0;
         loop {
             if ip >= chunk.code.len() { break; }
+            let (line, col) = chunk.lines[ip];
+            let error = |msg: String| RuntimeError { msg, line, col };
             let opcode = chunk.code[ip]; ip += 1;
             match opcode {
                 0 => break,
-                1 => { let idx = chunk.code[ip] as usize; self.push(chunk.constants[idx].clone()); ip += 1; }
-                2 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Number(a + b)); }
-                3 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Number(a - b)); }
-                6 => { let name = chunk.names[chunk.code[ip] as usize].clone(); let val = self.pop(); self.globals.insert(name, val); ip += 1; }
-                7 => { let name = &chunk.names[chunk.code[ip] as usize]; let val = self.globals.get(name).cloned().unwrap_or(Value::Nil); self.push(val); ip += 1; }
-                8 => { let val = self.pop(); match val { Value::Number(n) => println!("{}", n), Value::Str(s) => println!("{}", s), _ => {} } }
+                1 => { let idx = self.read_u16(&chunk.code, &mut ip); self.push(Value::from(chunk.constants[idx].clone())); }
+                2 => {
+                    let b = self.pop(); let a = self.pop();
+                    // Có một vế là chuỗi thì nối chuỗi, còn lại cộng số
+                    if matches!(a, Value::Str(_)) || matches!(b, Value::Str(_)) { self.push(Value::Str(format!("{}{}", a, b))); }
+                    else { self.push(arith(&a, &b, i64::checked_add, |x, y| x + y)); }
+                }
+                3 => { let b = self.pop(); let a = self.pop(); self.push(arith(&a, &b, i64::checked_sub, |x, y| x - y)); }
+                4 => { let b = self.pop(); let a = self.pop(); self.push(arith(&a, &b, i64::checked_mul, |x, y| x * y)); }
+                5 => { // luôn ra số thực để kiểu kết quả chỉ phụ thuộc kiểu hai vế
+                    let b = self.pop(); let a = self.pop();
+                    numbers(&a, &b, "/").map_err(error)?;
+                    if b.as_number() == 0.0 { return Err(error("chia cho 0".into())); }
+                    self.push(Value::Number(a.as_number() / b.as_number()));
+                }
+                53 => { // chia lấy phần nguyên, chỉ cho hai số nguyên
+                    let b = self.pop(); let a = self.pop();
+                    let (Value::Int(x), Value::Int(y)) = (&a, &b) else {
+                        let other = if matches!(a, Value::Int(_)) { &b } else { &a };
+                        return Err(error(format!("phép '\\' cần số nguyên nhưng nhận {} '{}'", other.type_name(), other)));
+                    };
+                    if *y == 0 { return Err(error("chia cho 0".into())); }
+                    self.push(Value::Int(x.checked_div(*y).ok_or_else(|| error("kết quả quá lớn cho kiểu nguyên".into()))?));
+                }
+                32 => {
+                    let b = self.pop(); let a = self.pop();
+                    numbers(&a, &b, "%").map_err(error)?;
+                    if b.as_number() == 0.0 { return Err(error("chia lấy dư cho 0".into())); }
+                    self.push(arith(&a, &b, i64::checked_rem, |x, y| x % y));
+                }
+                33 => { // số mũ nguyên không âm thì giữ số nguyên
+                    let b = self.pop(); let a = self.pop();
+                    let int_pow = |x: i64, y: i64| u32::try_from(y).ok().and_then(|y| x.checked_pow(y));
+                    self.push(arith(&a, &b, int_pow, f64::powf));
+                }
+                34 => {
+                    let val = match self.pop() {
+                        Value::Int(n) => n.checked_neg().map(Value::Int).unwrap_or(Value::Number(-(n as f64))),
+                        Value::Number(n) => Value::Number(-n),
+                        other => return Err(error(format!("không đổi dấu được '{}'", other))),
+                    };
+                    self.push(val);
+                }
+                35 => { let val = self.pop(); self.push(Value::Bool(!val.is_truthy())); }
+                36 => { let val = self.pop(); self.push(Value::Bool(val.is_truthy())); }
+                38 => { // vòng cho: ô slot, slot+1, slot+2 là biến đếm, giá trị cuối, bước
+                    let slot = self.base + chunk.code[ip] as usize;
+                    let inclusive = chunk.code[ip + 1] == 1; ip += 2;
+                    let exit = self.read_u16(&chunk.code, &mut ip);
+                    let vals = &self.stack[slot..slot + 3];
+                    if let Some(v) = vals.iter().find(|v| !matches!(v, Value::Int(_) | Value::Number(_))) {
+                        return Err(error(format!("vòng cho cần số nhưng nhận {} '{}'", v.type_name(), v)));
+                    }
+                    let (i, end, step) = (vals[0].as_number(), vals[1].as_number(), vals[2].as_number());
+                    if step == 0.0 { return Err(error("bước của vòng cho không được bằng 0".into())); }
+                    let done = match (step > 0.0, inclusive) { (true, true) => i > end, (true, false) => i >= end, (false, true) => i < end, (false, false) => i <= end };
+                    if done { ip = exit; }
+                }
+                39 => {
+                    let slot = self.base + chunk.code[ip] as usize; ip += 1;
+                    self.stack[slot] = arith(&self.stack[slot], &self.stack[slot + 2], i64::checked_add, |x, y| x + y);
+                }
+                40 => { // vòng moi: ô slot, slot+1, slot+2 là giá trị đang duyệt, vị trí kế tiếp, biến lặp
+                    let slot = self.base + chunk.code[ip] as usize; ip += 1;
+                    let exit = self.read_u16(&chunk.code, &mut ip);
+                    let pos = self.stack[slot + 1].as_number() as usize;
+                    let next = match &self.stack[slot] {
+                        // Với chuỗi, vị trí là offset byte để mỗi bước chỉ cắt một cụm ký tự
+                        Value::Str(s) => s[pos..].graphemes(true).next().map(|g| (Value::Str(g.to_string()), pos + g.len())),
+                        Value::List(items) => items.borrow().get(pos).map(|item| (item.clone(), pos + 1)),
+                        Value::Map(pairs) => pairs.borrow().get(pos).map(|(key, _)| (key.clone(), pos + 1)),
+                        other => return Err(error(format!("không lặp được qua giá trị kiểu {}", other.type_name()))),
+                    };
+                    match next {
+                        Some((item, pos)) => { self.stack[slot + 2] = item; self.stack[slot + 1] = Value::Int(pos as i64); }
+                        None => ip = exit,
+                    }
+                }
+                37 => { // kiểm tra kiểu của giá trị trên đỉnh stack; hằng thứ hai mô tả chỗ nhận giá trị
+                    let ty = &chunk.types[self.read_u16(&chunk.code, &mut ip)];
+                    let target = &chunk.constants[self.read_u16(&chunk.code, &mut ip)];
+                    let top = self.stack.len() - 1;
+                    let val = &mut self.stack[top];
+                    if !ty.accepts(val) {
+                        let target = if let vl_bytecode::Value::Chuoi(s) = target { s.as_str() } else { "" };
+                        let got = if let Value::Nil = val { "rỗng".to_string() } else { format!("{} '{}'", val.type_name(), val) };
+                        return Err(error(format!("{} có kiểu {} nhưng nhận {}", target, ty, got)));
+                    }
+                    if let (Type::Thuc, Value::Int(n)) = (ty, &*val) { *val = Value::Number(*n as f64); }
+                }
+                41 => { // gom n giá trị trên đỉnh stack thành danh sách
+                    let n = self.read_u16(&chunk.code, &mut ip);
+                    let items = self.pop_n(n);
+                    self.push(Value::List(Rc::new(RefCell::new(items))));
+                }
+                48 => { // gom n cặp khoá, giá trị trên đỉnh stack thành bảng; khoá trùng thì cặp sau thắng
+                    let n = self.read_u16(&chunk.code, &mut ip);
+                    let mut pairs: Vec<(Value, Value)> = Vec::with_capacity(n);
+                    let mut vals = self.pop_n(2 * n).into_iter();
+                    while let (Some(key), Some(val)) = (vals.next(), vals.next()) {
+                        key.check_key().map_err(error)?;
+                        match pairs.iter_mut().find(|(k, _)| k.equals(&key)) { Some(pair) => pair.1 = val, None => pairs.push((key, val)) }
+                    }
+                    self.push(Value::Map(Rc::new(RefCell::new(pairs))));
+                }
+                51 => { // tạo bản ghi từ giá trị các trường trên đỉnh stack
+                    let record = chunk.records[chunk.code[ip] as usize].clone(); ip += 1;
+                    let n = record.fields.len();
+                    let vals = self.pop_n(n);
+                    self.push(Value::Record(record, Rc::new(RefCell::new(vals))));
+                }
+                52 => { // tạo biến thể: chỉ số loại, chỉ số biến thể, giá trị mang theo nằm trên đỉnh stack
+                    let en = chunk.enums[chunk.code[ip] as usize].clone();
+                    let v = chunk.code[ip + 1] as usize; ip += 2;
+                    let n = en.variants[v].fields.len();
+                    let vals = self.pop_n(n);
+                    self.push(Value::Variant(en, v, Rc::new(vals)));
+                }
+                49 => { // co_khoa(m, k)
+                    let key = self.pop(); let object = self.pop();
+                    let Value::Map(pairs) = &object else { return Err(error(format!("co_khoa cần bảng nhưng nhận {} '{}'", object.type_name(), object))) };
+                    let found = pairs.borrow().iter().any(|(k, _)| k.equals(&key));
+                    self.push(Value::Bool(found));
+                }
+                50 => { // xoa_khoa(m, k) trả về giá trị bị xoá, rỗng nếu không có khoá đó
+                    let key = self.pop(); let object = self.pop();
+                    let Value::Map(pairs) = &object else { return Err(error(format!("xoa_khoa cần bảng nhưng nhận {} '{}'", object.type_name(), object))) };
+                    let mut pairs = pairs.borrow_mut();
+                    let removed = pairs.iter().position(|(k, _)| k.equals(&key)).map(|i| pairs.remove(i).1).unwrap_or(Value::Nil);
+                    drop(pairs);
+                    self.push(removed);
+                }
+                42 => { // đọc ds[i] hoặc m[k]; với chuỗi thì lấy cụm ký tự thứ i
+                    let index = self.pop(); let object = self.pop();
+                    let val = match &object {
+                        Value::List(items) => { let items = items.borrow(); items[resolve_index(&index, items.len(), "danh sách").map_err(error)?].clone() }
+                        Value::Str(s) => {
+                            let chars: Vec<&str> = s.graphemes(true).collect();
+                            Value::Str(chars[resolve_index(&index, chars.len(), "chuỗi").map_err(error)?].to_string())
+                        }
+                        Value::Map(pairs) => match pairs.borrow().iter().find(|(k, _)| k.equals(&index)) {
+                            Some((_, val)) => val.clone(),
+                            None => return Err(error(format!("bảng không có khoá {}", index.repr()))),
+                        },
+                        Value::Record(record, vals) => vals.borrow()[record.field(&index).map_err(error)?].clone(),
+                        Value::Variant(en, v, vals) => vals[en.variants[*v].field(&index).map_err(error)?].clone(),
+                        other => return Err(error(format!("không lấy phần tử được từ giá trị kiểu {}", other.type_name()))),
+                    };
+                    self.push(val);
+                }
+                43 => { // ghi ds[i] = x hoặc m[k] = x; là câu lệnh nên không để lại gì trên stack
+                    let val = self.pop(); let index = self.pop(); let object = self.pop();
+                    match &object {
+                        Value::List(items) => {
+                            let mut items = items.borrow_mut();
+                            let pos = resolve_index(&index, items.len(), "danh sách").map_err(error)?;
+                            items[pos] = val;
+                        }
+                        Value::Map(pairs) => {
+                            index.check_key().map_err(error)?;
+                            let mut pairs = pairs.borrow_mut();
+                            match pairs.iter_mut().find(|(k, _)| k.equals(&index)) { Some(pair) => pair.1 = val, None => pairs.push((index, val)) }
+                        }
+                        Value::Record(record, vals) => vals.borrow_mut()[record.field(&index).map_err(error)?] = val,
+                        other => return Err(error(format!("chỉ gán phần tử được cho danh sách, bảng hoặc bản ghi, không phải kiểu {}", other.type_name()))),
+                    }
+                }
+                44 => { // cắt ds[a:b] ra bản sao mới
+                    let end = self.pop(); let start = self.pop(); let object = self.pop();
+                    let val = match &object {
+                        Value::List(items) => {
+                            let items = items.borrow();
+                            let (a, b) = (slice_bound(&start, items.len(), 0).map_err(error)?, slice_bound(&end, items.len(), items.len()).map_err(error)?);
+                            Value::List(Rc::new(RefCell::new(items.get(a..b).unwrap_or_default().to_vec())))
+                        }
+                        Value::Str(s) => {
+                            let chars: Vec<&str> = s.graphemes(true).collect();
+                            let (a, b) = (slice_bound(&start, chars.len(), 0).map_err(error)?, slice_bound(&end, chars.len(), chars.len()).map_err(error)?);
+                            Value::Str(chars.get(a..b).unwrap_or_default().concat())
+                        }
+                        other => return Err(error(format!("không cắt được giá trị kiểu {}", other.type_name()))),
+                    };
+                    self.push(val);
+                }
+                45 => { // them(ds, x)
+                    let val = self.pop(); let object = self.pop();
+                    let Value::List(items) = &object else { return Err(error(format!("them cần danh sách nhưng nhận {} '{}'", object.type_name(), object))) };
+                    items.borrow_mut().push(val);
+                    self.push(Value::Nil);
+                }
+                46 => { // xoa_tai(ds, i) trả về phần tử bị xoá
+                    let index = self.pop(); let object = self.pop();
+                    let Value::List(items) = &object else { return Err(error(format!("xoa_tai cần danh sách nhưng nhận {} '{}'", object.type_name(), object))) };
+                    let mut items = items.borrow_mut();
+                    let pos = resolve_index(&index, items.len(), "danh sách").map_err(error)?;
+                    let removed = items.remove(pos);
+                    drop(items);
+                    self.push(removed);
+                }
+                47 => { // do_dai: số phần tử của danh sách, số cặp của bảng hoặc số ký tự của chuỗi
+                    let len = match self.pop() {
+                        Value::List(items) => items.borrow().len(),
+                        Value::Map(pairs) => pairs.borrow().len(),
+                        Value::Str(s) => s.graphemes(true).count(),
+                        other => return Err(error(format!("do_dai cần danh sách, bảng hoặc chuỗi nhưng nhận {} '{}'", other.type_name(), other))),
+                    };
+                    self.push(Value::Int(len as i64));
+                }
+                12 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Bool(a > b)); }
+                13 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Bool(a < b)); }
+                14 => { let b = self.pop(); let a = self.pop(); self.push(Value::Bool(a.equals(&b))); }
+                16 => { let b = self.pop(); let a = self.pop(); self.push(Value::Bool(!a.equals(&b))); }
+                19 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Bool(a <= b)); }
+                26 => { let b = self.pop().as_number(); let a = self.pop().as_number(); self.push(Value::Bool(a >= b)); }
+                6 => { let name = chunk.names[self.read_u16(&chunk.code, &mut ip)].clone(); let val = self.pop(); self.globals.insert(name, val); }
+                7 => { let name = &chunk.names[self.read_u16(&chunk.code, &mut ip)]; let val = self.globals.get(name).cloned().unwrap_or(Value::Nil); self.push(val); }
+                8 => { let val = self.pop(); if !matches!(val, Value::Nil) { writeln!(self.out, "{}", val).ok(); } self.push(Value::Nil); }
+                9 => { self.pop(); }
+                29 => { let slot = self.base + chunk.code[ip] as usize; ip += 1; self.push(self.stack[slot].clone()); }
+                30 => { let slot = self.base + chunk.code[ip] as usize; ip += 1; self.stack[slot] = self.pop(); }
+                31 => { let f = chunk.functions[chunk.code[ip] as usize].clone(); ip += 1; self.push(Value::Function(Rc::new(f))); }
+                18 => { // gọi hàm: stack là [hàm, đối số 1, ..., đối số n]
+                    let argc = chunk.code[ip] as usize; ip += 1;
+                    let Value::Function(f) = self.stack[self.stack.len() - argc - 1].clone() else {
+                        return Err(error(format!("'{}' không phải là hàm", self.stack[self.stack.len() - argc - 1])));
+                    };
+                    if f.arity != argc { return Err(error(format!("hàm '{}' cần {} tham số nhưng nhận {}", f.name, f.arity, argc))); }
+                    if self.call_stack.len() >= MAX_FRAMES { return Err(error(format!("tràn stack khi gọi '{}', có thể do đệ quy không dừng", f.name))); }
+                    self.call_stack.push((ip, self.base));
+                    self.base = self.stack.len() - argc;
+                    ip = f.start;
+                }
+                17 => { // trả về: bỏ cả khung gọi lẫn giá trị hàm, để lại kết quả
+                    let result = self.pop();
+                    let Some((ret, base)) = self.call_stack.pop() else { break };
+                    self.stack.truncate(self.base - 1);
+                    self.push(result);
+                    self.base = base;
+                    ip = ret;
+                }
                 10 => { let target = self.read_u16(&chunk.code, &mut ip); let cond = self.pop(); if !cond.is_truthy() { ip = target; } }
                 11 => { ip = self.read_u16(&chunk.code, &mut ip); }
-                15 => { let r = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() % 5) + 1; self.push(Value::Number(r as f64)); }
-                21 => { let ms = self.pop().as_number(); thread::sleep(Duration::from_millis(ms as u64)); }
-                22 => { print!("{esc}[2J{esc}[1;1H", esc = 27 as char); io::stdout().flush().unwrap(); } 
+                27 => { // định dạng giá trị trên đỉnh stack theo chuỗi hằng
+                    let spec = if let vl_bytecode::Value::Chuoi(s) = &chunk.constants[self.read_u16(&chunk.code, &mut ip)] { FormatSpec::parse(s) } else { None };
+                    let val = self.pop();
+                    let text = spec.map(|s| s.apply(&val)).unwrap_or_else(|| val.to_string());
+                    self.push(Value::Str(text));
+                }
+                28 => { // ghép n giá trị trên đỉnh stack thành một chuỗi
+                    let n = chunk.code[ip] as usize; ip += 1;
+                    let mut parts = vec![String::new(); n];
+                    for part in parts.iter_mut().rev() { *part = self.pop().to_string(); }
+                    self.push(Value::Str(parts.concat()));
+                }
+                15 => { let r = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() % 5) + 1; self.push(Value::Int(r as i64)); }
+                21 => { let ms = self.pop().as_number(); thread::sleep(Duration::from_millis(ms as u64)); self.push(Value::Nil); }
+                22 => { write!(self.out, "{esc}[2J{esc}[1;1H", esc = 27 as char).ok(); self.out.flush().ok(); self.push(Value::Nil); }
                 23 => { 
-                    io::stdout().flush().unwrap();
+                    self.out.flush().ok();
                     let mut input = String::new();
                     io::stdin().read_line(&mut input).ok();
                     self.push(Value::Str(input.trim().to_string()));
                 }
-                20 => { // doc_file
+                20 => { // doc_tep
                     let p = if let Value::Str(s) = self.pop() { s } else { "save_game.txt".to_string() };
                     let content = fs::read_to_string(p).unwrap_or("0".to_string());
                     self.push(Value::Str(content));
@@ -70,14 +569,17 @@ impl VM {
                     let content = if let Value::Str(s) = content_val { s } else { "0".to_string() };
                     let path = if let Value::Str(s) = path_val { s } else { "save_game.txt".to_string() };
                     fs::write(path, content).ok();
+                    self.push(Value::Nil);
                 }
                 25 => {
                     let val = self.pop();
-                    match val { Value::Number(n) => print!("{}", n), Value::Str(s) => print!("{}", s), _ => {} }
-                    io::stdout().flush().unwrap();
+                    if !matches!(val, Value::Nil) { write!(self.out, "{}", val).ok(); }
+                    self.out.flush().ok();
+                    self.push(Value::Nil);
                 }
                 _ => {}
             }
         }
+        Ok(())
     }
 }
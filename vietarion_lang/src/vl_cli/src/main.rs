@@ -1,39 +1,103 @@
 use std::fs;
-use std::env;
+use clap::{Parser as ClapParser, Subcommand};
+use colored::Colorize;
 use vl_core::lexer::Lexer;
 use vl_core::parser::Parser;
 use vl_core::compiler::Compiler;
+use vl_core::token::Dialect;
+use vl_core::translate::translate;
 use vl_vm::VM;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        println!("Cú pháp: cargo run -p vl_cli -- run <file_name>");
-        return;
-    }
+#[derive(ClapParser)]
+#[command(name = "vl", about = "Trình thông dịch Vietarion")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    let file_path = &args[2];
-    let source = fs::read_to_string(file_path).expect("Không đọc được file");
+#[derive(Subcommand)]
+enum Command {
+    /// Chạy một file .vl
+    Run {
+        file: String,
+        /// Bật cờ cho #[neu_cau_hinh(...)], có thể lặp lại
+        #[arg(long = "cfg")]
+        cfg: Vec<String>,
+        /// Bộ từ khoá khi file không có dòng #[ngon_ngu(..)]
+        #[arg(long, value_parser = ["vi", "en"], default_value = "vi")]
+        dialect: String,
+    },
+    /// Chạy file .vl cùng các câu lệnh #[kiem_thu]
+    Test {
+        file: String,
+        #[arg(long = "cfg")]
+        cfg: Vec<String>,
+        #[arg(long, value_parser = ["vi", "en"], default_value = "vi")]
+        dialect: String,
+    },
+    /// Dịch từ khoá của file sang tiếng Anh hoặc tiếng Việt
+    Translate {
+        file: String,
+        #[arg(long, value_parser = ["vi", "en"])]
+        to: String,
+        /// Bộ từ khoá của file khi nó không có dòng #[ngon_ngu(..)]
+        #[arg(long, value_parser = ["vi", "en"], default_value = "vi")]
+        from: String,
+        /// Ghi ra file này thay vì in ra màn hình
+        #[arg(short, long)]
+        out: Option<String>,
+    },
+}
 
-    // 1. Lexing
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.scan_tokens(); 
+fn main() {
+    let cli = Cli::parse();
+    let (file_path, cfg, tests, dialect) = match cli.command {
+        Command::Run { file, cfg, dialect } => (file, cfg, false, dialect),
+        Command::Test { file, cfg, dialect } => (file, cfg, true, dialect),
+        Command::Translate { file, from, to, out } => return translate_file(&file, &from, &to, out),
+    };
+    let source = fs::read_to_string(&file_path).expect("Không đọc được file");
+    let dialect = Dialect::from_name(&dialect).unwrap_or_default();
 
-    // 2. Parsing
-    let mut parser = Parser::new(tokens);
-    let stmts = match parser.parse() {
+    // 1. Lexing + 2. Parsing: parser kéo token từ lexer khi cần
+    let mut parser = Parser::new(Lexer::new(&source).with_dialect(dialect));
+    let stmts = parser.parse();
+    for w in &parser.warnings { eprintln!("{} {}", "Cảnh báo:".yellow(), w); }
+    let stmts = match stmts {
         Ok(s) => s,
-        Err(e) => {
-            println!("Lỗi Parser: {:?}", e);
+        Err(errors) => {
+            for e in errors { println!("{} {}", "Lỗi cú pháp:".red(), e); }
             return;
         }
     };
 
     // 3. Compiling
-    let mut compiler = Compiler::new();
+    let mut compiler = Compiler::new().with_cfg(&cfg);
+    if tests { compiler = compiler.with_tests(); }
     let chunk = compiler.compile(stmts);
+    for w in &compiler.warnings { eprintln!("{} {}", "Cảnh báo:".yellow(), w); }
+    let chunk = match chunk {
+        Ok(c) => c,
+        Err(errors) => {
+            for e in errors { println!("{} {}", "Lỗi biên dịch:".red(), e); }
+            return;
+        }
+    };
 
     // 4. Running
     let mut vm = VM::new();
-    vm.run(chunk);
+    if let Err(e) = vm.run(chunk) { println!("{} {}", "Lỗi khi chạy:".red(), e); }
+}
+
+fn translate_file(file: &str, from: &str, to: &str, out: Option<String>) {
+    let source = fs::read_to_string(file).expect("Không đọc được file");
+    let from = Dialect::from_name(from).unwrap_or_default();
+    let to = Dialect::from_name(to).unwrap_or_default();
+    match translate(&source, from, to) {
+        Ok(text) => match out {
+            Some(path) => fs::write(path, text).expect("Không ghi được file"),
+            None => print!("{}", text),
+        },
+        Err(errors) => for e in errors { println!("{} {}", "Lỗi dịch:".red(), e); },
+    }
 }